@@ -1,3 +1,5 @@
+use crate::output::Format;
+use crate::store::Storage;
 use clap::{ColorChoice, CommandFactory, Parser, Subcommand, ValueEnum};
 
 /// Detect --no-color from raw args (before clap parses).
@@ -18,6 +20,14 @@ pub fn filtered_args() -> Vec<String> {
     about = "See your environment clearly. Change it with precision.",
 )]
 pub struct Cli {
+    /// Report results as text or as one JSON document (default: $ENVISION_FORMAT or human)
+    #[arg(long, global = true, value_enum)]
+    pub format: Option<Format>,
+
+    /// Write JSON output to this file descriptor instead of stderr (default: $ENVISION_JSON_FD)
+    #[arg(long, global = true, value_name = "FD")]
+    pub json_fd: Option<u32>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -48,7 +58,28 @@ pub enum Command {
     },
 
     /// Display session status (exits 0 if clean, 1 if dirty)
-    Status,
+    Status {
+        /// List each tracked change with what made it and when
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// List variables that differ from the baseline or between two environments
+    ///
+    /// `diff [FILTER]` compares the baseline with the live environment.
+    /// `diff <A> <B>` compares any two of: `baseline`, `live`, a snapshot
+    /// (name or number), an exported session file or an env file of
+    /// `NAME=value` lines. Prefix a side with `snap:` or `file:` when a
+    /// snapshot and a file share its name.
+    Diff {
+        #[arg(value_name = "A B", num_args = 0..=2)]
+        sides: Vec<String>,
+
+        /// Only variables matching this name or glob (e.g. 'AWS_*');
+        /// unchanged matches are listed too
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
 
     /// Set and track an environment variable
     Set {
@@ -87,12 +118,62 @@ pub enum Command {
         shell: Shell,
     },
 
+    /// Track changes made outside envision so that clear can revert them
+    Adopt {
+        /// Variables to adopt
+        vars: Vec<String>,
+
+        /// Adopt every untracked change
+        #[arg(long, conflicts_with = "vars")]
+        all: bool,
+    },
+
     /// Remove all tracked changes, restore to baseline
     Clear {
         /// Skip confirmation prompt
         #[arg(long)]
         force: bool,
     },
+
+    /// Revert the last N operations
+    Undo {
+        /// Number of operations to undo
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+
+    /// Reapply the last N undone operations
+    Redo {
+        /// Number of operations to redo
+        #[arg(default_value_t = 1)]
+        count: usize,
+    },
+
+    /// Show a variable's value, where it came from and its earlier values
+    Get {
+        /// Variable name
+        var: String,
+    },
+
+    /// Show the journal of changes made in this session
+    Log {
+        /// Only show changes to this variable
+        var: Option<String>,
+
+        /// Only show changes made by this profile
+        #[arg(long)]
+        profile: Option<String>,
+
+        /// Show only the last N entries
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+
+    /// Save, inspect and restore environment snapshots
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
 }
 
 #[derive(Clone, ValueEnum)]
@@ -113,5 +194,107 @@ pub enum SessionAction {
         /// Resume an existing session instead of creating a new one
         #[arg(long, conflicts_with = "force")]
         resume: bool,
+
+        /// Keep original values (not just hashes) so clear can restore them.
+        /// Also enabled by ENVISION_FULL_BASELINE=1.
+        #[arg(long)]
+        full_baseline: bool,
+
+        /// Where to keep session data (default: ENVISION_STORE, else env)
+        #[arg(long, value_enum)]
+        store: Option<Storage>,
+
+        /// Start a child session nested under the session inherited from the
+        /// parent shell; clear then only reverts this shell's own changes
+        #[arg(long, conflicts_with_all = ["force", "resume", "full_baseline"])]
+        child: bool,
+
+        /// Label shown in the banner and status instead of the session id
+        #[arg(long, value_name = "LABEL", conflicts_with = "resume")]
+        name: Option<String>,
+    },
+
+    /// Replace damaged session data with a fresh session, keeping whatever
+    /// can be salvaged (tracked changes, snapshots, change log)
+    Repair,
+
+    /// Stop tracking and remove envision's variables from the shell
+    End {
+        /// Revert tracked changes first, as 'envision clear' would
+        #[arg(long, conflicts_with = "keep")]
+        revert: bool,
+
+        /// Leave tracked changes applied
+        #[arg(long)]
+        keep: bool,
+    },
+
+    /// Save the session (baseline, tracked changes, profile) to a JSON file
+    Export {
+        /// File to write
+        file: String,
+
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Apply the tracked changes from an exported session file
+    Import {
+        /// File written by 'envision session export'
+        file: String,
+
+        /// Skip confirmation prompt
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Accept the current environment as the new baseline, keeping tracked changes
+    Rebase,
+
+    /// Give this shell its own copy of a session inherited from another shell
+    Fork,
+
+    /// Make this shell the owner of an inherited or abandoned session
+    Claim,
+
+    /// Stop tracking the session in this shell (changes stay applied)
+    Discard,
+
+    /// Remove session files left behind by shells that have exited
+    Prune,
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Save the current environment as a new snapshot
+    Save {
+        /// Optional snapshot name (sequence numbers are always assigned)
+        name: Option<String>,
+    },
+
+    /// List saved snapshots
+    List,
+
+    /// Show a snapshot and what restoring it would change
+    Show {
+        /// Snapshot name or sequence number
+        snapshot: String,
+    },
+
+    /// Restore the environment to exactly a snapshot's state
+    Restore {
+        /// Snapshot name or sequence number
+        snapshot: String,
+
+        /// Skip confirmation prompt
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Delete a snapshot
+    Delete {
+        /// Snapshot name or sequence number
+        snapshot: String,
     },
 }
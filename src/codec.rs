@@ -0,0 +1,374 @@
+use crate::schema;
+use crate::session::{Salvage, Session, hash_bytes};
+use base64::{Engine, engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Marks the compact format: `z:` followed by unpadded URL-safe base64 of a
+/// deflated body. The body starts with a layout byte and an 8-byte FNV-1a
+/// checksum of the rest, then holds the baseline as delta-encoded names with
+/// raw 8-byte hashes and the rest of the session as JSON. The checksum is
+/// unkeyed: it catches truncated or garbled payloads, not deliberate edits.
+/// Payloads without a prefix are the original plain base64 JSON format.
+pub const COMPACT_PREFIX: &str = "z:";
+
+/// Body layout written by this build.
+const BODY_LAYOUT: u8 = 1;
+
+/// Encode a session in the compact format.
+pub fn encode(session: &Session) -> Result<String, String> {
+    let mut rest = session.clone();
+    let baseline = std::mem::take(&mut rest.baseline);
+
+    let mut body = Vec::new();
+    write_baseline(&mut body, &baseline);
+    serde_json::to_writer(&mut body, &rest)
+        .map_err(|e| format!("Failed to serialize session: {e}"))?;
+
+    let mut framed = vec![BODY_LAYOUT];
+    framed.extend_from_slice(&hash_bytes(&body).to_le_bytes());
+    framed.extend_from_slice(&body);
+    deflate(&framed)
+        .map(|compressed| format!("{COMPACT_PREFIX}{}", URL_SAFE_NO_PAD.encode(compressed)))
+}
+
+/// Decode a session payload in the compact or legacy format.
+pub fn decode(encoded: &str) -> Result<Session, String> {
+    let Some(data) = encoded.strip_prefix(COMPACT_PREFIX) else {
+        return decode_legacy(encoded);
+    };
+    let body = inflate(data)?;
+    match body.split_first() {
+        Some((&BODY_LAYOUT, rest)) => decode_body(rest),
+        Some((layout, _)) => Err(format!(
+            "Session was written by a newer envision (payload layout {layout}, this build \
+             supports {BODY_LAYOUT}); upgrade envision to keep using it"
+        )),
+        None => Err("Session data corrupted (empty payload)".into()),
+    }
+}
+
+fn inflate(data: &str) -> Result<Vec<u8>, String> {
+    let compressed = URL_SAFE_NO_PAD
+        .decode(data)
+        .map_err(|e| format!("Session data corrupted (bad base64): {e}"))?;
+
+    let mut body = Vec::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .read_to_end(&mut body)
+        .map_err(|e| format!("Session data corrupted (bad compression): {e}"))?;
+    Ok(body)
+}
+
+fn decode_body(body: &[u8]) -> Result<Session, String> {
+    let (sum, mut cursor) = body.split_first_chunk::<8>()
+        .ok_or("Session data corrupted (missing checksum)")?;
+    if u64::from_le_bytes(*sum) != hash_bytes(cursor) {
+        return Err("Session data corrupted (checksum mismatch)".into());
+    }
+    let baseline = read_baseline(&mut cursor)?;
+    let mut session = from_json(cursor)?;
+    session.baseline = baseline;
+    Ok(session)
+}
+
+fn decode_legacy(encoded: &str) -> Result<Session, String> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Session data corrupted (bad base64): {e}"))?;
+    std::str::from_utf8(&bytes)
+        .map_err(|e| format!("Session data corrupted (bad utf8): {e}"))?;
+    from_json(&bytes)
+}
+
+/// Parse session JSON, migrating older schema versions first.
+fn from_json(json: &[u8]) -> Result<Session, String> {
+    let doc = serde_json::from_slice(json)
+        .map_err(|e| format!("Session data corrupted (bad json): {e}"))?;
+    serde_json::from_value(schema::migrate(doc)?)
+        .map_err(|e| format!("Session data corrupted (bad json): {e}"))
+}
+
+/// Recover whatever survives in a payload that failed to decode: tracked
+/// changes, snapshots and the journal, each kept only if it parses on its
+/// own. Truncated payloads are decoded as far as they go. 00-R9
+pub fn salvage(encoded: &str) -> Salvage {
+    let Some(json) = salvage_json(encoded) else {
+        return Salvage::default();
+    };
+
+    match serde_json::from_slice::<Value>(&json) {
+        Ok(doc) => Salvage {
+            tracked: field(&doc, "tracked"),
+            snapshots: field(&doc, "snapshots"),
+            journal: field(&doc, "journal"),
+        },
+        Err(_) => Salvage {
+            tracked: scan_field(&json, "tracked"),
+            snapshots: scan_field(&json, "snapshots"),
+            journal: scan_field(&json, "journal"),
+        },
+    }
+}
+
+/// Best-effort extraction of the session JSON from a damaged payload.
+fn salvage_json(encoded: &str) -> Option<Vec<u8>> {
+    let Some(data) = encoded.strip_prefix(COMPACT_PREFIX) else {
+        return lenient_base64(&STANDARD, encoded);
+    };
+
+    let body = inflate_partial(&lenient_base64(&URL_SAFE_NO_PAD, data)?);
+    if body.first() != Some(&BODY_LAYOUT) {
+        return None;
+    }
+    // Skip the layout byte and checksum in front of the baseline
+    let mut cursor = body.get(9..)?;
+    read_baseline(&mut cursor).ok()?;
+    Some(cursor.to_vec())
+}
+
+/// Decode base64, retrying without a truncated trailing group.
+fn lenient_base64(engine: &impl Engine, data: &str) -> Option<Vec<u8>> {
+    engine.decode(data).ok().or_else(|| {
+        let whole = data.get(..data.len() / 4 * 4)?;
+        engine.decode(whole).ok()
+    })
+}
+
+/// Inflate as much as possible, keeping the output produced before any error.
+fn inflate_partial(compressed: &[u8]) -> Vec<u8> {
+    let mut decoder = DeflateDecoder::new(compressed);
+    let mut body = Vec::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(n) = decoder.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+    body
+}
+
+fn deflate(body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(body)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| format!("Failed to compress session: {e}"))
+}
+
+/// Parse the value following the first `"key":` in truncated JSON. Fields
+/// are serialized in struct order, so the first occurrence of the top-level
+/// keys comes before any nested snapshot fields of the same name.
+fn scan_field<T: DeserializeOwned>(json: &[u8], key: &str) -> Option<T> {
+    let needle = format!("\"{key}\":");
+    let start = json.windows(needle.len()).position(|w| w == needle.as_bytes())? + needle.len();
+    let value = serde_json::Deserializer::from_slice(&json[start..])
+        .into_iter::<Value>()
+        .next()?
+        .ok()?;
+    from_value(&value)
+}
+
+/// A field of intact JSON. Empty collections are not serialized, so a
+/// missing field is recovered as empty.
+fn field<T: DeserializeOwned + Default>(doc: &Value, key: &str) -> Option<T> {
+    match doc.get(key) {
+        Some(value) => from_value(value),
+        None => Some(T::default()),
+    }
+}
+
+fn from_value<T: DeserializeOwned>(value: &Value) -> Option<T> {
+    serde_json::from_value(value.clone()).ok()
+}
+
+/// Baseline layout: entry count, then per entry the length of the prefix
+/// shared with the previous name, the remaining suffix, and the hash.
+/// Names arrive sorted, so shared prefixes (`XDG_`, `LC_`, ...) are common.
+fn write_baseline(out: &mut Vec<u8>, baseline: &BTreeMap<String, u64>) {
+    write_varint(out, baseline.len() as u64);
+    let mut prev: &[u8] = &[];
+    for (name, hash) in baseline {
+        let name = name.as_bytes();
+        let shared = prev.iter().zip(name).take_while(|(a, b)| a == b).count();
+        write_varint(out, shared as u64);
+        write_varint(out, (name.len() - shared) as u64);
+        out.extend_from_slice(&name[shared..]);
+        out.extend_from_slice(&hash.to_le_bytes());
+        prev = name;
+    }
+}
+
+fn read_baseline(input: &mut &[u8]) -> Result<BTreeMap<String, u64>, String> {
+    let corrupted = || "Session data corrupted (bad baseline)".to_string();
+
+    let count = read_varint(input).ok_or_else(corrupted)?;
+    let mut baseline = BTreeMap::new();
+    let mut prev: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let shared = read_varint(input).ok_or_else(corrupted)? as usize;
+        let len = read_varint(input).ok_or_else(corrupted)? as usize;
+        if shared > prev.len() || len.checked_add(8).is_none_or(|n| input.len() < n) {
+            return Err(corrupted());
+        }
+        let (suffix, rest) = input.split_at(len);
+        let (hash, rest) = rest.split_at(8);
+        *input = rest;
+
+        prev.truncate(shared);
+        prev.extend_from_slice(suffix);
+        let name = String::from_utf8(prev.clone()).map_err(|_| corrupted())?;
+        let hash = u64::from_le_bytes(hash.try_into().expect("8-byte slice"));
+        baseline.insert(name, hash);
+    }
+    Ok(baseline)
+}
+
+/// LEB128 unsigned varint.
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SourceKind;
+
+    fn big_env() -> BTreeMap<String, String> {
+        (0..300)
+            .map(|i| (format!("XDG_VAR_{i:03}"), format!("/usr/share/value/{i}")))
+            .collect()
+    }
+
+    #[test]
+    fn compact_roundtrip() {
+        let mut session = Session::new(&big_env()).unwrap();
+        session.track_set("FOO", "bar", None, SourceKind::Set);
+        let encoded = encode(&session).unwrap();
+        assert!(encoded.starts_with(COMPACT_PREFIX));
+
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(decoded.id, session.id);
+        assert_eq!(decoded.baseline, session.baseline);
+        assert!(decoded.tracked.contains_key("FOO"));
+    }
+
+    #[test]
+    fn compact_is_smaller_than_legacy() {
+        let session = Session::new(&big_env()).unwrap();
+        let legacy = STANDARD.encode(serde_json::to_string(&session).unwrap());
+        let compact = encode(&session).unwrap();
+        assert!(compact.len() * 2 < legacy.len(), "{} vs {}", compact.len(), legacy.len());
+    }
+
+    #[test]
+    fn legacy_payload_still_decodes() {
+        let session = Session::new(&big_env()).unwrap();
+        let legacy = STANDARD.encode(serde_json::to_string(&session).unwrap());
+        let decoded = decode(&legacy).unwrap();
+        assert_eq!(decoded.baseline, session.baseline);
+    }
+
+    #[test]
+    fn original_unversioned_payload_migrates() {
+        let original = r#"{"id":"c111ac57","created_at":1700000000,"baseline":{"FOO":1},"tracked":{"BAR":{"Set":{"value":"1","previous":null}}}}"#;
+        let decoded = decode(&STANDARD.encode(original)).unwrap();
+        assert_eq!(decoded.version, schema::CURRENT_VERSION);
+        assert_eq!(decoded.baseline.get("FOO"), Some(&1));
+        assert!(decoded.tracked.contains_key("BAR"));
+    }
+
+    #[test]
+    fn truncated_payload_is_rejected() {
+        let encoded = encode(&Session::new(&big_env()).unwrap()).unwrap();
+        assert!(decode(&encoded[..encoded.len() / 2]).is_err());
+    }
+
+    /// Inflate a compact payload, change its body and re-encode it without
+    /// updating the checksum.
+    fn corrupt(encoded: &str, edit: impl FnOnce(&mut Vec<u8>)) -> String {
+        let data = encoded.strip_prefix(COMPACT_PREFIX).unwrap();
+        let mut body = inflate_partial(&URL_SAFE_NO_PAD.decode(data).unwrap());
+        edit(&mut body);
+        format!("{COMPACT_PREFIX}{}", URL_SAFE_NO_PAD.encode(deflate(&body).unwrap()))
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let mut session = Session::new(&big_env()).unwrap();
+        session.track_set("FOO", "bar", None, SourceKind::Set);
+        let encoded = encode(&session).unwrap();
+        let corrupted = corrupt(&encoded, |body| {
+            let at = body.windows(5).position(|w| w == b"\"bar\"").unwrap();
+            body[at + 3] = b'z';
+        });
+
+        let err = decode(&corrupted).unwrap_err();
+        assert!(err.contains("checksum mismatch"), "{err}");
+
+        // Content is intact apart from the edit, so everything is salvaged
+        let salvage = salvage(&corrupted);
+        assert!(salvage.lost().is_empty());
+        assert!(salvage.tracked.as_ref().unwrap().contains_key("FOO"));
+    }
+
+    #[test]
+    fn unknown_layout_asks_for_upgrade() {
+        let encoded = encode(&Session::new(&big_env()).unwrap()).unwrap();
+        let newer = corrupt(&encoded, |body| body[0] = BODY_LAYOUT + 1);
+        let err = decode(&newer).unwrap_err();
+        assert!(err.contains("newer envision"), "{err}");
+    }
+
+    #[test]
+    fn truncated_payload_salvages_leading_fields() {
+        let mut session = Session::new(&BTreeMap::new()).unwrap();
+        session.track_set("FOO", "bar", None, SourceKind::Set);
+        for i in 0..20 {
+            session.save_snapshot(Some(&format!("snap-{i}")), &big_env()).unwrap();
+        }
+        let encoded = encode(&session).unwrap();
+        let truncated = &encoded[..encoded.len() * 3 / 4];
+        assert!(decode(truncated).is_err());
+
+        let salvage = salvage(truncated);
+        assert!(salvage.tracked.as_ref().unwrap().contains_key("FOO"));
+        assert!(salvage.snapshots.is_none());
+        assert_eq!(salvage.lost(), vec!["snapshots", "change log"]);
+    }
+
+    #[test]
+    fn garbage_salvages_nothing() {
+        let salvage = salvage("z:not base64 at all!");
+        assert_eq!(salvage.lost(), vec!["tracked changes", "snapshots", "change log"]);
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        for n in [0, 1, 127, 128, 300, u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, n);
+            assert_eq!(read_varint(&mut buf.as_slice()), Some(n));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::export::Exports;
+use crate::ignore::Ignore;
+use crate::output::Output;
+use crate::session::{self, Session, Source, SourceKind, TrackedChange};
+use std::collections::BTreeMap;
+
+/// Turn untracked changes into tracked ones so that clear can revert them.
+/// The environment itself is left as it is.
+pub fn run(out: &Output, ex: &mut Exports, vars: &[String], all: bool) -> Result<u8, String> {
+    if vars.is_empty() && !all {
+        return Err("Name the variables to adopt, or use --all to adopt every untracked change.".into());
+    }
+    for var in vars {
+        session::validate_var_name(var)?;
+    }
+
+    let mut session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+
+    // Ignored variables are only adopted when named explicitly
+    let candidates: Vec<String> = if all {
+        session::list_untracked(&session, &env, &Ignore::load())
+            .iter()
+            .map(|change| change.var().to_string())
+            .collect()
+    } else {
+        vars.to_vec()
+    };
+
+    let mut adopted = Vec::new();
+    for var in &candidates {
+        if session.tracked.contains_key(var) {
+            out.warn(&format!("{var} is already tracked"));
+            continue;
+        }
+        match untracked_change(&session, var, env.get(var)) {
+            Ok(Some((kind, change))) => adopted.push((var, kind, change)),
+            Ok(None) if !all => out.warn(&format!("{var} has no untracked change")),
+            Ok(None) => {}
+            Err(e) => out.warn(&e),
+        }
+    }
+
+    if adopted.is_empty() {
+        out.info("Nothing to adopt");
+        return Ok(0);
+    }
+
+    // The values stay as they are; undo only stops tracking them again
+    session.record("adopt", None, adopted.iter()
+        .map(|(var, _, _)| (var.to_string(), env.get(*var).cloned(), env.get(*var).cloned())));
+    for (var, _, change) in &adopted {
+        session.tracked.insert(var.to_string(), change.clone());
+    }
+    ex.save_session(&session)?;
+
+    out.success(&format!("Adopted {} variable(s):", adopted.len()));
+    for (var, kind, _) in adopted {
+        out.info(&format!("  {var} ({kind})"));
+    }
+    Ok(0)
+}
+
+/// Describe how `var` differs from the baseline as a tracked change, or
+/// `None` if it doesn't. Modifications and removals need the original value,
+/// or clear would unset the variable instead of restoring it.
+fn untracked_change(
+    session: &Session,
+    var: &str,
+    current: Option<&String>,
+) -> Result<Option<(&'static str, TrackedChange)>, String> {
+    let original = |what: &str| {
+        session.original_value(var).map(str::to_string).ok_or_else(|| format!(
+            "Cannot adopt {what} of {var}: its original value is unknown. Start sessions \
+             with --full-baseline to keep original values, or run 'envision session rebase' \
+             to accept the change instead."
+        ))
+    };
+    let source = Some(Source::now(SourceKind::Adopt));
+    let change = match (session.in_baseline(var), current) {
+        (false, Some(value)) => ("added", TrackedChange::Set { value: value.clone(), previous: None, source }),
+        (true, Some(value)) if session.baseline_changed(var, value) => {
+            let previous = Some(original("modification")?);
+            ("modified", TrackedChange::Set { value: value.clone(), previous, source })
+        }
+        (true, None) => ("removed", TrackedChange::Unset { previous: original("removal")?, source }),
+        _ => return Ok(None),
+    };
+    Ok(Some(change))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::env;
+
+    #[test]
+    fn classifies_untracked_changes() {
+        let mut session = Session::new(&env(&[("KEEP", "1"), ("EDIT", "old"), ("GONE", "x")])).unwrap();
+        session.capture_originals(&env(&[("KEEP", "1"), ("EDIT", "old"), ("GONE", "x")]));
+
+        let added = untracked_change(&session, "NEW", Some(&"v".to_string())).unwrap();
+        assert!(matches!(added, Some(("added", TrackedChange::Set { previous: None, .. }))));
+
+        let modified = untracked_change(&session, "EDIT", Some(&"new".to_string())).unwrap();
+        assert!(matches!(modified, Some(("modified", TrackedChange::Set { previous: Some(p), .. })) if p == "old"));
+
+        let removed = untracked_change(&session, "GONE", None).unwrap();
+        assert!(matches!(removed, Some(("removed", TrackedChange::Unset { previous, .. })) if previous == "x"));
+
+        assert!(untracked_change(&session, "KEEP", Some(&"1".to_string())).unwrap().is_none());
+    }
+
+    #[test]
+    fn changes_without_original_cannot_be_adopted() {
+        let session = Session::new(&env(&[("GONE", "x"), ("PATH", "/bin")])).unwrap();
+        assert!(untracked_change(&session, "GONE", None).is_err());
+        assert!(untracked_change(&session, "PATH", Some(&"/nvm:/bin".to_string())).is_err());
+        assert!(untracked_change(&session, "NEW", Some(&"v".to_string())).unwrap().is_some());
+    }
+}
@@ -1,14 +1,28 @@
+use crate::output::Output;
 use crate::session::Session;
+use serde_json::json;
 
 /// Print the banner line to stdout for testing/debugging.
 /// In normal use, the shell hook renders the banner directly from env vars.
-pub fn run() -> Result<u8, String> {
+/// With `--format json` the parts are reported instead.
+pub fn run(out: &Output) -> Result<u8, String> {
     let profile = std::env::var("ENVISION_PROFILE").unwrap_or_default();
     let session = Session::load().ok().flatten();
 
+    out.field("profile", (!profile.is_empty()).then_some(&profile));
+    out.field("session", session.as_ref().map(|sess| json!({
+        "id": sess.id,
+        "name": sess.name,
+        "tracked": sess.tracked.len(),
+        "state": if dirty() { "dirty" } else { "clean" },
+    })));
+
     if profile.is_empty() && session.is_none() {
         return Ok(1);
     }
+    if out.is_json() {
+        return Ok(0);
+    }
 
     let columns: usize = std::env::var("COLUMNS")
         .ok()
@@ -32,6 +46,11 @@ pub fn run() -> Result<u8, String> {
     Ok(0)
 }
 
+/// Dirty flag kept up to date by mutating commands.
+fn dirty() -> bool {
+    std::env::var("ENVISION_DIRTY").is_ok_and(|v| v == "1")
+}
+
 fn render_content(profile: &str, session: Option<&Session>) -> String {
     let mut parts = Vec::new();
 
@@ -41,9 +60,9 @@ fn render_content(profile: &str, session: Option<&Session>) -> String {
 
     if let Some(sess) = session {
         let tracked = sess.tracked.len();
-        let dirty = std::env::var("ENVISION_DIRTY").unwrap_or_else(|_| "0".into());
-        let state = if dirty == "1" { "dirty" } else { "clean" };
-        parts.push(format!("{}  {}  {}", sess.id, tracked, state));
+        let state = if dirty() { "dirty" } else { "clean" };
+        let label = sess.name.as_deref().unwrap_or(&sess.id);
+        parts.push(format!("{label}  {tracked}  {state}"));
     }
 
     if parts.is_empty() {
@@ -77,10 +96,24 @@ mod tests {
             created_at: 0,
             baseline: BTreeMap::new(),
             tracked: BTreeMap::new(),
+            ..Session::new(&BTreeMap::new()).unwrap()
         };
         let content = render_content("dev", Some(&session));
         assert!(content.contains("dev"));
         assert!(content.contains("abc123"));
         assert!(content.contains("clean"));
     }
+
+    #[test]
+    fn render_prefers_session_name() {
+        use std::collections::BTreeMap;
+        let session = Session {
+            id: "abc123".into(),
+            name: Some("release-debug".into()),
+            ..Session::new(&BTreeMap::new()).unwrap()
+        };
+        let content = render_content("", Some(&session));
+        assert!(content.contains("release-debug"));
+        assert!(!content.contains("abc123"));
+    }
 }
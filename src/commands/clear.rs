@@ -1,36 +1,75 @@
+use crate::commands::{snapshot, status};
 use crate::export::Exports;
-use crate::output::Output;
-use crate::session::{Session, TrackedChange};
+use crate::output::{self, Output};
+use crate::pathlist;
+use crate::session::{self, Session, TrackedChange};
+use serde_json::json;
 use std::io::{self, IsTerminal, Write};
 
 /// 05-R1 through 05-R14
 pub fn run(out: &Output, ex: &mut Exports, force: bool) -> Result<u8, String> {
-    // 05-R1, 05-R13: require active session with baseline
+    // 05-R1, 05-R13: require an active, readable session with baseline;
+    // damaged data is refused with a pointer to 'session repair'
     let mut session = Session::load()?
         .ok_or("No active session. Run 'envision session init' first.")?;
 
+    // 05-R3: preview changes before applying
+    let (to_unset, to_restore) = preview_changes(&session);
+    out.field("cleared", session.tracked.iter()
+        .map(|(var, change)| status::tracked_json(&session, var, change))
+        .collect::<Vec<_>>());
+    out.field("removed", &to_unset);
+    out.field("restored", to_restore.iter()
+        .map(|(var, value)| json!({ "var": var, "value": session::redact(var, value) }))
+        .collect::<Vec<_>>());
+
     // 05-R12: nothing to clear
     if session.tracked.is_empty() {
         out.success("Nothing to clear");
         return Ok(0);
     }
 
-    // 05-R3: preview changes before applying
-    let (to_unset, to_restore) = preview_changes(&session);
-
     out.info(&format!("{} tracked change(s) to clear:", session.tracked.len()));
+    if let Some(lineage) = &session.parent {
+        out.info(&format!("  {}", out.dim(&format!(
+            "(this shell's changes only; session {} keeps its own)", lineage.parent_id
+        ))));
+    }
+    // What made each change, for sessions that recorded it
+    let source = |var: &str| match session.tracked.get(var).and_then(TrackedChange::source) {
+        Some(source) => format!(" {}", out.dim(&format!(
+            "(from {}, {})", source.describe(), output::format_timestamp(source.at)
+        ))),
+        None => String::new(),
+    };
     for var in &to_unset {
-        out.info(&format!("  unset {var}"));
+        // Overwritten baseline var without a stored original (hash-only baseline)
+        if session.in_baseline(var) {
+            out.info(&format!("  unset {var} {}{}", out.dim("(original value unknown)"), source(var)));
+        } else {
+            out.info(&format!("  unset {var}{}", source(var)));
+        }
     }
     for (var, value) in &to_restore {
-        out.info(&format!("  restore {var}={value}"));
+        let entries = std::env::var(var).ok()
+            .and_then(|current| pathlist::describe_change(var, &current, value));
+        match entries {
+            Some(entries) => out.info(&format!("  restore {var}: {entries}{}", source(var))),
+            None => out.info(&format!("  restore {var}={value}{}", source(var))),
+        }
     }
 
     // 05-R2: require confirmation unless --force
     if !force {
+        if out.is_json() {
+            return Err("Confirmation required: use --force with --format json".into());
+        }
         prompt_confirmation()?;
     }
 
+    // 05-R4: snapshot before clearing so a mistaken clear can be undone
+    snapshot::auto(&mut session, "before clear");
+
     // 05-R5: remove variables that were set through the tool
     for var in &to_unset {
         ex.unset_var(var);
@@ -42,6 +81,11 @@ pub fn run(out: &Output, ex: &mut Exports, force: bool) -> Result<u8, String> {
     }
 
     // Clear tracked state in session
+    let changes = to_unset.iter()
+        .map(|var| (var.clone(), std::env::var(var).ok(), None))
+        .chain(to_restore.iter()
+            .map(|(var, value)| (var.clone(), std::env::var(var).ok(), Some(value.clone()))));
+    session.record("clear", None, changes);
     session.tracked.clear();
     ex.save_session(&session)?;
 
@@ -60,7 +104,7 @@ pub fn run(out: &Output, ex: &mut Exports, force: bool) -> Result<u8, String> {
 /// Separate tracked changes into variables to unset and variables to restore.
 /// 05-R5: Set vars get unset (they were added by the tool).
 /// 05-R6: Unset vars get restored to their previous value.
-fn preview_changes(session: &Session) -> (Vec<String>, Vec<(String, String)>) {
+pub fn preview_changes(session: &Session) -> (Vec<String>, Vec<(String, String)>) {
     let mut to_unset = Vec::new();
     let mut to_restore = Vec::new();
 
@@ -74,7 +118,7 @@ fn preview_changes(session: &Session) -> (Vec<String>, Vec<(String, String)>) {
                     None => to_unset.push(var.clone()),
                 }
             }
-            TrackedChange::Unset { previous } => {
+            TrackedChange::Unset { previous, .. } => {
                 // Was unset — restore it
                 to_restore.push((var.clone(), previous.clone()));
             }
@@ -109,6 +153,7 @@ fn prompt_confirmation() -> Result<(), String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session::SourceKind;
     use std::collections::BTreeMap;
 
     fn session_with_tracked() -> Session {
@@ -120,15 +165,18 @@ mod tests {
         tracked.insert("NEW_VAR".into(), TrackedChange::Set {
             value: "added".into(),
             previous: None,
+            source: None,
         });
         // An overwritten var (has previous)
         tracked.insert("EXISTING".into(), TrackedChange::Set {
             value: "changed".into(),
             previous: Some("original".into()),
+            source: None,
         });
         // An unset var
         tracked.insert("REMOVED".into(), TrackedChange::Unset {
             previous: "was_here".into(),
+            source: None,
         });
 
         Session {
@@ -136,6 +184,7 @@ mod tests {
             created_at: 0,
             baseline,
             tracked,
+            ..Session::new(&BTreeMap::new()).unwrap()
         }
     }
 
@@ -168,6 +217,19 @@ mod tests {
         assert_eq!(to_restore.len(), 2);
     }
 
+    #[test]
+    fn set_then_unset_on_hash_only_baseline_restores_nothing() {
+        let env = BTreeMap::from([("FOO".to_string(), "original".to_string())]);
+        let mut session = Session::new(&env).unwrap();
+        session.track_set("FOO", "x", None, SourceKind::Set);
+        session.track_unset("FOO", Some("x"), SourceKind::Unset);
+
+        // The original was never known, so clear must not put back "x"
+        let (to_unset, to_restore) = preview_changes(&session);
+        assert!(to_unset.is_empty());
+        assert!(to_restore.is_empty());
+    }
+
     #[test]
     fn preview_empty_session() {
         let session = Session {
@@ -175,6 +237,7 @@ mod tests {
             created_at: 0,
             baseline: BTreeMap::new(),
             tracked: BTreeMap::new(),
+            ..Session::new(&BTreeMap::new()).unwrap()
         };
         let (to_unset, to_restore) = preview_changes(&session);
         assert!(to_unset.is_empty());
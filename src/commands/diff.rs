@@ -0,0 +1,632 @@
+use crate::commands::profile::{self, EnvChange};
+use crate::fingerprint::Hasher;
+use crate::ignore::{self, Ignore};
+use crate::output::Output;
+use crate::pathlist;
+use crate::session::{self, Session, TrackedChange, UntrackedChange};
+use crate::session_file::SessionFile;
+use crate::snapshot::Snapshot;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// How a variable differs from the baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+}
+
+/// Who made the difference, in the terms `status` uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Changed through envision; `clear` reverts it.
+    Tracked,
+    /// Changed outside envision.
+    Untracked,
+    /// Baseline value, untouched.
+    Original,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub var: String,
+    pub kind: Kind,
+    pub origin: Origin,
+    /// Value at the baseline, if known. Always `None` for added variables.
+    pub old: Option<String>,
+    /// Current value; `None` for removed variables.
+    pub new: Option<String>,
+    /// Entries added or removed, for list variables such as PATH.
+    pub entries: Option<String>,
+}
+
+/// `diff [--filter F]` or `diff <A> <B> [--filter F]`.
+pub fn run(out: &Output, sides: &[String], filter: Option<&str>) -> Result<u8, String> {
+    match sides {
+        [] => baseline(out, filter),
+        [a, b] => between(out, a, b, filter),
+        _ => Err("Name two sides to compare, or none to compare against the baseline. \
+                  Filter variables with --filter.".into()),
+    }
+}
+
+/// List every variable that differs from the baseline. With a filter (a
+/// variable name or glob), matching variables that still hold their
+/// original value are listed too.
+fn baseline(out: &Output, filter: Option<&str>) -> Result<u8, String> {
+    let session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+
+    let entries = session_diff(&session, &env, &Ignore::load(), filter);
+    if entries.is_empty() {
+        match filter {
+            Some(filter) => out.info(&format!("No variables match '{filter}'")),
+            None => out.success("No changes since the baseline"),
+        }
+        return Ok(0);
+    }
+
+    for entry in &entries {
+        out.info(&format_entry(out, entry));
+    }
+
+    let changed: Vec<&Entry> = entries.iter().filter(|e| e.kind != Kind::Unchanged).collect();
+    if !changed.is_empty() {
+        let tracked = changed.iter().filter(|e| e.origin == Origin::Tracked).count();
+        out.info(&format!(
+            "{} change(s): {tracked} tracked, {} untracked",
+            changed.len(),
+            changed.len() - tracked
+        ));
+    }
+    Ok(0)
+}
+
+/// Compare `env` against the session's baseline: untracked changes as
+/// `list_untracked` finds them, plus the tracked ones. Unchanged variables
+/// are listed only when a filter asks for them by name.
+pub fn session_diff(
+    session: &Session,
+    env: &BTreeMap<String, String>,
+    ignore: &Ignore,
+    filter: Option<&str>,
+) -> Vec<Entry> {
+    let wanted = |var: &str| filter.is_none_or(|f| ignore::glob_match(f, var));
+
+    let mut entries: Vec<Entry> = session::list_untracked(session, env, ignore)
+        .into_iter()
+        .filter(|change| wanted(change.var()))
+        .map(|change| {
+            let kind = match change {
+                UntrackedChange::Added(_) => Kind::Added,
+                UntrackedChange::Removed(_) => Kind::Removed,
+                UntrackedChange::Modified(_) => Kind::Modified,
+            };
+            entry(session, env, change.var(), kind, Origin::Untracked)
+        })
+        .collect();
+
+    for var in session.tracked.keys().filter(|var| wanted(var) && !session::is_envision_var(var)) {
+        match baseline_kind(session, var, env.get(var)) {
+            Some(Kind::Unchanged) if filter.is_none() => {}
+            Some(kind) => entries.push(entry(session, env, var, kind, Origin::Tracked)),
+            None => {}
+        }
+    }
+
+    if filter.is_some() {
+        let untouched = session.baseline.keys().filter(|var| {
+            wanted(var) && !session.tracked.contains_key(*var) && !session::is_envision_var(var)
+        });
+        for var in untouched {
+            if baseline_kind(session, var, env.get(var)) == Some(Kind::Unchanged) {
+                entries.push(entry(session, env, var, Kind::Unchanged, Origin::Original));
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.var.cmp(&b.var));
+    entries
+}
+
+/// How `var` with `current` value differs from the baseline, or `None` if
+/// it is in neither.
+fn baseline_kind(session: &Session, var: &str, current: Option<&String>) -> Option<Kind> {
+    match (session.in_baseline(var), current) {
+        (true, None) => Some(Kind::Removed),
+        (true, Some(value)) if session.baseline_changed(var, value) => Some(Kind::Modified),
+        (true, Some(_)) => Some(Kind::Unchanged),
+        (false, Some(_)) => Some(Kind::Added),
+        (false, None) => None,
+    }
+}
+
+fn entry(session: &Session, env: &BTreeMap<String, String>, var: &str, kind: Kind, origin: Origin) -> Entry {
+    let current = env.get(var);
+    let old = match (kind, session.tracked.get(var)) {
+        (Kind::Added, _) => None,
+        (Kind::Unchanged, _) => current.cloned(),
+        (_, Some(TrackedChange::Set { previous: Some(previous), .. }))
+        | (_, Some(TrackedChange::Unset { previous, .. })) => Some(previous.clone()),
+        _ => session.original_value(var).map(str::to_string),
+    };
+    let list = match (kind, current) {
+        (Kind::Modified, Some(value)) => session.list_change(var, value),
+        _ => None,
+    };
+    Entry { var: var.to_string(), kind, origin, old, new: current.cloned(), entries: list }
+}
+
+/// One side of `diff <A> <B>`.
+pub struct Side {
+    pub label: String,
+    /// Variables whose value is known.
+    pub known: BTreeMap<String, String>,
+    /// Baseline variables stored as a hash only.
+    pub hashed: BTreeMap<String, u64>,
+    /// Hashes values the way `hashed` was computed.
+    pub hasher: Hasher,
+    /// Set for an exported session, which only says something about the
+    /// variables it tracks. Whole environments are `None`.
+    pub scope: Option<BTreeSet<String>>,
+    /// Set for snapshots, which leave out sensitive variables.
+    pub omits_sensitive: bool,
+}
+
+impl Side {
+    fn whole(label: String, known: BTreeMap<String, String>) -> Self {
+        Self {
+            label,
+            known,
+            hashed: BTreeMap::new(),
+            hasher: Hasher::default(),
+            scope: None,
+            omits_sensitive: false,
+        }
+    }
+
+    fn state(&self, var: &str) -> State {
+        match (self.known.get(var), self.hashed.get(var)) {
+            (Some(value), _) => State::Value(value.clone()),
+            (None, Some(hash)) => State::Hashed(*hash),
+            (None, None) => State::Unset,
+        }
+    }
+
+    fn covers(&self, var: &str) -> bool {
+        if self.omits_sensitive && session::is_sensitive_var(var) {
+            return false;
+        }
+        self.scope.as_ref().is_none_or(|scope| scope.contains(var))
+    }
+}
+
+/// A variable on one side of a comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Unset,
+    Value(String),
+    /// In a baseline that only kept the hash.
+    Hashed(u64),
+}
+
+/// Resolve `baseline`, `live`, a snapshot of the current session, or a file
+/// (an exported session or an env file). `snap:` and `file:` pick one
+/// explicitly; a bare name that is both is refused rather than guessed.
+/// The session is only needed for the baseline and snapshots, so a missing
+/// or damaged one doesn't get in the way of comparing files.
+pub fn resolve_side(
+    spec: &str,
+    session: &Result<Option<Session>, String>,
+    env: &BTreeMap<String, String>,
+) -> Result<Side, String> {
+    let require_session = || match session {
+        Ok(Some(session)) => Ok(session),
+        Ok(None) => Err(format!("No active session, so there is no {spec}. Run 'envision session init' first.")),
+        Err(e) => Err(e.clone()),
+    };
+
+    if let Some(name) = spec.strip_prefix("snap:") {
+        let snap = require_session()?.find_snapshot(name)
+            .ok_or_else(|| format!("No snapshot '{name}'. Run 'envision snapshot list' to see snapshots."))?;
+        return Ok(snapshot_side(snap));
+    }
+    if let Some(path) = spec.strip_prefix("file:") {
+        return file_side(path);
+    }
+    match spec {
+        "live" => return Ok(Side::whole("live environment".into(), env.clone())),
+        "baseline" => {
+            let session = require_session()?;
+            if let Some(problem) = &session.baseline_problem {
+                return Err(format!("{problem}. Run 'envision session rebase' to start a new baseline."));
+            }
+            return Ok(baseline_side(session));
+        }
+        _ => {}
+    }
+
+    let snap = session.as_ref().ok().and_then(Option::as_ref).and_then(|s| s.find_snapshot(spec));
+    match (snap, Path::new(spec).exists()) {
+        (Some(_), true) => Err(format!(
+            "'{spec}' is both a snapshot and a file: use snap:{spec} or file:{spec}"
+        )),
+        (Some(snap), false) => Ok(snapshot_side(snap)),
+        (None, true) => file_side(spec),
+        (None, false) => match session {
+            Err(e) => Err(format!("No file named '{spec}', and snapshots cannot be read: {e}")),
+            Ok(_) => Err(format!("No snapshot or file named '{spec}'")),
+        },
+    }
+}
+
+/// Snapshots leave sensitive variables out, so those are not compared.
+fn snapshot_side(snap: &Snapshot) -> Side {
+    let mut side = Side::whole(format!("snapshot {}", snap.label()), snap.env.clone());
+    side.omits_sensitive = true;
+    side
+}
+
+/// An exported session or an env file.
+fn file_side(spec: &str) -> Result<Side, String> {
+    let text = std::fs::read_to_string(spec)
+        .map_err(|e| format!("Cannot read {spec}: {e}"))?;
+
+    if text.trim_start().starts_with('{') {
+        let imported = SessionFile::parse(&text)
+            .and_then(|file| file.session())
+            .map_err(|e| format!("{spec}: {e}"))?;
+        Ok(session_file_side(spec, &imported))
+    } else {
+        let known = parse_env_file(&text).map_err(|e| format!("{spec}: {e}"))?;
+        Ok(Side::whole(spec.to_string(), known))
+    }
+}
+
+/// The baseline with every value that can be recovered: stored originals and
+/// the previous values of tracked changes. The rest stays a hash.
+fn baseline_side(session: &Session) -> Side {
+    let mut known = session.originals.clone().unwrap_or_default();
+    for (var, change) in &session.tracked {
+        let previous = match change {
+            TrackedChange::Set { previous, .. } => previous.as_ref(),
+            TrackedChange::Unset { previous, .. } => Some(previous),
+        };
+        if let Some(previous) = previous {
+            known.insert(var.clone(), previous.clone());
+        }
+    }
+    known.retain(|var, _| session.in_baseline(var));
+
+    let hashed = session.baseline.iter()
+        .filter(|(var, _)| !known.contains_key(*var))
+        .map(|(var, hash)| (var.clone(), *hash))
+        .collect();
+
+    Side {
+        label: "baseline".into(),
+        known,
+        hashed,
+        hasher: session.hasher,
+        scope: None,
+        omits_sensitive: false,
+    }
+}
+
+/// An exported session holds its tracked changes, not a whole environment.
+fn session_file_side(path: &str, imported: &Session) -> Side {
+    let mut side = Side::whole(format!("{path} (session {})", imported.id), BTreeMap::new());
+    for (var, change) in &imported.tracked {
+        if let TrackedChange::Set { value, .. } = change {
+            side.known.insert(var.clone(), value.clone());
+        }
+    }
+    side.scope = Some(imported.tracked.keys().cloned().collect());
+    side
+}
+
+/// `NAME=value` lines as written by `env` or found in dotenv files. Blank
+/// lines and `#` comments are skipped, a leading `export` and quotes around
+/// the value are dropped. NUL-separated output (`env -0`) is read as is.
+pub fn parse_env_file(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut env = BTreeMap::new();
+
+    if text.contains('\0') {
+        for entry in text.split('\0').filter(|e| !e.is_empty()) {
+            let (var, value) = entry.split_once('=')
+                .ok_or_else(|| format!("entry '{entry}' is not NAME=value"))?;
+            env.insert(var.to_string(), value.to_string());
+        }
+        return Ok(env);
+    }
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (var, value) = line.split_once('=')
+            .ok_or_else(|| format!("line {}: expected NAME=value", n + 1))?;
+        session::validate_var_name(var).map_err(|e| format!("line {}: {e}", n + 1))?;
+
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(value);
+        env.insert(var.to_string(), value.to_string());
+    }
+    Ok(env)
+}
+
+/// Differences from `a` to `b`. Known values go through
+/// `profile::compute_diff`; hash-only baseline variables are compared by
+/// hash. Variables outside either side's scope are left out.
+pub fn compare(a: &Side, b: &Side, ignore: &Ignore, filter: Option<&str>) -> Vec<(String, State, State)> {
+    let wanted = |var: &str| {
+        a.covers(var) && b.covers(var) && !ignore.is_ignored(var)
+            && filter.is_none_or(|f| ignore::glob_match(f, var))
+    };
+
+    // compute_diff skips these for the known values
+    let hashed: BTreeSet<&String> = a.hashed.keys()
+        .chain(b.hashed.keys())
+        .filter(|var| wanted(var) && !session::is_envision_var(var))
+        .collect();
+
+    let mut changes = Vec::new();
+    for var in &hashed {
+        let (old, new) = (a.state(var), b.state(var));
+        let same = match (&old, &new) {
+            (State::Hashed(x), State::Hashed(y)) => x == y,
+            (State::Hashed(hash), State::Value(value)) => a.hasher.hash(value) == *hash,
+            (State::Value(value), State::Hashed(hash)) => b.hasher.hash(value) == *hash,
+            _ => false,
+        };
+        if !same {
+            changes.push((var.to_string(), old, new));
+        }
+    }
+
+    let values = |side: &Side| -> BTreeMap<String, String> {
+        side.known.iter()
+            .filter(|(var, _)| wanted(var) && !hashed.contains(var))
+            .map(|(var, value)| (var.clone(), value.clone()))
+            .collect()
+    };
+    for change in profile::compute_diff(&values(a), &values(b)) {
+        let (var, new) = match change {
+            EnvChange::Set(var, value) => (var, State::Value(value)),
+            EnvChange::Unset(var) => (var, State::Unset),
+        };
+        changes.push((var.clone(), a.state(&var), new));
+    }
+
+    changes.sort_by(|x, y| x.0.cmp(&y.0));
+    changes
+}
+
+/// List the differences between two sides.
+fn between(out: &Output, a: &str, b: &str, filter: Option<&str>) -> Result<u8, String> {
+    let session = Session::load();
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+    let a = resolve_side(a, &session, &env)?;
+    let b = resolve_side(b, &session, &env)?;
+
+    let changes = compare(&a, &b, &Ignore::load(), filter);
+    out.info(&format!("Comparing {} -> {}", out.bold(&a.label), out.bold(&b.label)));
+    if changes.is_empty() {
+        out.success("No differences");
+        return Ok(0);
+    }
+
+    for (var, old, new) in &changes {
+        let (sign, kind) = match (old, new) {
+            (State::Unset, _) => (out.green("+"), "added"),
+            (_, State::Unset) => (out.red("-"), "removed"),
+            _ => (out.yellow("~"), "modified"),
+        };
+        let change = match (old, new) {
+            (State::Value(old), State::Value(new)) => pathlist::describe_change(var, old, new),
+            _ => None,
+        };
+        let change = change.unwrap_or_else(|| format!("{} -> {}", describe(out, old), describe(out, new)));
+        out.info(&format!("  {sign} {}  {}  {change}", out.bold(var), out.dim(kind)));
+    }
+    out.info(&format!("{} difference(s)", changes.len()));
+    Ok(0)
+}
+
+fn describe(out: &Output, state: &State) -> String {
+    match state {
+        State::Value(value) => value.clone(),
+        State::Hashed(_) => out.dim("(unknown)"),
+        State::Unset => out.dim("(unset)"),
+    }
+}
+
+fn format_entry(out: &Output, entry: &Entry) -> String {
+    let (sign, kind) = match entry.kind {
+        Kind::Added => (out.green("+"), "added"),
+        Kind::Removed => (out.red("-"), "removed"),
+        Kind::Modified => (out.yellow("~"), "modified"),
+        Kind::Unchanged => (out.dim("="), "unchanged"),
+    };
+    let origin = match entry.origin {
+        Origin::Tracked => "tracked",
+        Origin::Untracked => "untracked",
+        Origin::Original => "original",
+    };
+
+    let old = match (&entry.old, entry.kind) {
+        (Some(value), _) => value.clone(),
+        (None, Kind::Added) => out.dim("(unset)"),
+        (None, _) => out.dim("(unknown)"),
+    };
+    // PATH and friends: which entries changed, not both whole values
+    let change = match (entry.kind, &entry.new, &entry.entries) {
+        (Kind::Unchanged, _, _) => old,
+        (_, Some(_), Some(entries)) => entries.clone(),
+        (_, Some(new), None) => format!("{old} -> {new}"),
+        (_, None, _) => format!("{old} -> {}", out.dim("(unset)")),
+    };
+
+    format!("  {sign} {}  {}  {change}", out.bold(&entry.var), out.dim(&format!("{kind}, {origin}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SourceKind;
+    use crate::testutil::env;
+
+    fn session() -> Session {
+        let base = env(&[("KEEP", "1"), ("EDIT", "old"), ("GONE", "x"), ("PWD", "/home")]);
+        let mut session = Session::new(&base).unwrap();
+        session.capture_originals(&base);
+        session.track_set("NEW", "v", None, SourceKind::Set);
+        session
+    }
+
+    fn find<'a>(entries: &'a [Entry], var: &str) -> Option<&'a Entry> {
+        entries.iter().find(|e| e.var == var)
+    }
+
+    #[test]
+    fn categorizes_changes() {
+        let now = env(&[("KEEP", "1"), ("EDIT", "new"), ("NEW", "v"), ("PWD", "/tmp")]);
+        let entries = session_diff(&session(), &now, &Ignore::defaults(), None);
+
+        let new = find(&entries, "NEW").unwrap();
+        assert_eq!((new.kind, new.origin), (Kind::Added, Origin::Tracked));
+
+        let edit = find(&entries, "EDIT").unwrap();
+        assert_eq!((edit.kind, edit.origin), (Kind::Modified, Origin::Untracked));
+        assert_eq!(edit.old.as_deref(), Some("old"));
+
+        let gone = find(&entries, "GONE").unwrap();
+        assert_eq!((gone.kind, gone.origin), (Kind::Removed, Origin::Untracked));
+        assert!(gone.new.is_none());
+
+        assert!(find(&entries, "KEEP").is_none());
+        assert!(find(&entries, "PWD").is_none());
+    }
+
+    #[test]
+    fn tracked_but_unchanged_needs_a_filter() {
+        let mut session = session();
+        session.track_set("KEEP", "1", None, SourceKind::Set);
+        let now = env(&[("KEEP", "1"), ("EDIT", "old"), ("GONE", "x"), ("NEW", "v")]);
+        assert!(find(&session_diff(&session, &now, &Ignore::defaults(), None), "KEEP").is_none());
+
+        let entries = session_diff(&session, &now, &Ignore::defaults(), Some("KEEP"));
+        assert_eq!((entries[0].kind, entries[0].origin), (Kind::Unchanged, Origin::Tracked));
+    }
+
+    #[test]
+    fn filter_includes_original_values() {
+        let now = env(&[("KEEP", "1"), ("EDIT", "new")]);
+        let entries = session_diff(&session(), &now, &Ignore::defaults(), Some("KEEP"));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].origin, Origin::Original);
+        assert_eq!(entries[0].old.as_deref(), Some("1"));
+
+        let entries = session_diff(&session(), &now, &Ignore::defaults(), Some("E*"));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].var, "EDIT");
+    }
+
+    #[test]
+    fn list_variables_show_changed_entries() {
+        let base = env(&[("PATH", "/usr/bin:/bin")]);
+        let mut session = Session::new(&base).unwrap();
+        session.capture_originals(&base);
+        session.track_set("PATH", "/opt:/usr/bin:/bin", None, SourceKind::Set);
+
+        let now = env(&[("PATH", "/opt:/usr/bin")]);
+        let entries = session_diff(&session, &now, &Ignore::defaults(), None);
+        assert_eq!(entries[0].entries.as_deref(), Some("prepended /opt; removed /bin"));
+    }
+
+    #[test]
+    fn parses_env_files() {
+        let text = "# saved\nexport A=1\nB=\"two words\"\n\nC='x=y'\n";
+        assert_eq!(parse_env_file(text).unwrap(), env(&[("A", "1"), ("B", "two words"), ("C", "x=y")]));
+        assert_eq!(parse_env_file("A=1\0B=a\nb\0").unwrap(), env(&[("A", "1"), ("B", "a\nb")]));
+        assert!(parse_env_file("A=1\nnot a var\n").unwrap_err().starts_with("line 2"));
+    }
+
+    #[test]
+    fn compares_hashed_baseline_with_values() {
+        let base = env(&[("KEEP", "1"), ("EDIT", "old"), ("GONE", "x")]);
+        let session = Session::new(&base).unwrap();
+        let session = Ok(Some(session));
+        let baseline = resolve_side("baseline", &session, &base).unwrap();
+        assert!(baseline.known.is_empty());
+
+        let now = env(&[("KEEP", "1"), ("EDIT", "new"), ("NEW", "v")]);
+        let live = resolve_side("live", &session, &now).unwrap();
+        let changes = compare(&baseline, &live, &Ignore::defaults(), None);
+
+        let vars: Vec<&str> = changes.iter().map(|c| c.0.as_str()).collect();
+        assert_eq!(vars, ["EDIT", "GONE", "NEW"]);
+        assert!(matches!(changes[0].1, State::Hashed(_)));
+        assert_eq!(changes[2], ("NEW".into(), State::Unset, State::Value("v".into())));
+    }
+
+    #[test]
+    fn snapshots_leave_sensitive_vars_out() {
+        let now = env(&[("A", "1"), ("API_TOKEN", "secret")]);
+        let mut session = Session::new(&now).unwrap();
+        session.save_snapshot(Some("snap"), &now).unwrap();
+
+        let session = Ok(Some(session));
+        let snap = resolve_side("snap", &session, &now).unwrap();
+        let live = resolve_side("live", &session, &env(&[("A", "2"), ("API_TOKEN", "other")])).unwrap();
+        let changes = compare(&snap, &live, &Ignore::defaults(), None);
+        assert_eq!(changes, [("A".into(), State::Value("1".into()), State::Value("2".into()))]);
+    }
+
+    #[test]
+    fn ambiguous_names_need_a_prefix() {
+        // Tests run from the crate root, where this file exists
+        let mut session = Session::new(&BTreeMap::new()).unwrap();
+        session.save_snapshot(Some("Cargo.toml"), &env(&[("A", "1")])).unwrap();
+        let session = Ok(Some(session));
+
+        let err = resolve_side("Cargo.toml", &session, &BTreeMap::new()).err().unwrap();
+        assert!(err.contains("snap:Cargo.toml"), "{err}");
+        let snap = resolve_side("snap:Cargo.toml", &session, &BTreeMap::new()).unwrap();
+        assert_eq!(snap.known, env(&[("A", "1")]));
+    }
+
+    #[test]
+    fn files_compare_without_a_usable_session() {
+        let path = std::env::temp_dir().join(format!("envision-diff-{}.env", std::process::id()));
+        std::fs::write(&path, "A=1\n").unwrap();
+        let damaged = Err("Session data corrupted".to_string());
+
+        let file = resolve_side(path.to_str().unwrap(), &damaged, &BTreeMap::new());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.unwrap().known, env(&[("A", "1")]));
+        assert!(resolve_side("baseline", &damaged, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn session_files_only_cover_tracked_vars() {
+        let mut exported = Session::new(&env(&[("GONE", "x")])).unwrap();
+        exported.track_set("A", "1", None, SourceKind::Set);
+        exported.track_unset("GONE", Some("x"), SourceKind::Unset);
+        let exported = session_file_side("saved.json", &exported);
+
+        let other = Side::whole("env".into(), env(&[("A", "2"), ("GONE", "x"), ("HOME", "/root")]));
+        let changes = compare(&other, &exported, &Ignore::defaults(), None);
+        assert_eq!(changes, [
+            ("A".into(), State::Value("2".into()), State::Value("1".into())),
+            ("GONE".into(), State::Value("x".into()), State::Unset),
+        ]);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::commands::log;
+use crate::journal::JournalEntry;
+use crate::output::{self, Output};
+use crate::session::{PreviousKind, Session, Source, TrackedChange};
+
+/// What the session knows about one variable.
+pub struct Provenance<'a> {
+    /// Category of the current value; `None` when the variable is not set
+    /// and never was in this session.
+    pub kind: Option<PreviousKind>,
+    /// What made the current value, for tracked values.
+    pub source: Option<&'a Source>,
+    /// Journal entry that produced the current value, for tracked values.
+    /// Names the command for changes tracked before sources were recorded.
+    pub entry: Option<&'a JournalEntry>,
+    /// Every journaled change to the variable, oldest first.
+    pub history: Vec<&'a JournalEntry>,
+}
+
+/// Show a variable's value, its category, what set it and its history.
+pub fn run(out: &Output, var: &str) -> Result<u8, String> {
+    let current = std::env::var(var).ok();
+    match &current {
+        Some(value) => out.info(&format!("{}={value}", out.bold(var))),
+        None => out.info(&format!("{} is not set", out.bold(var))),
+    }
+
+    let Some(session) = Session::load()? else {
+        out.info(&out.dim("No active session: provenance is not tracked"));
+        return Ok(0);
+    };
+
+    let found = provenance(&session, var, current.as_deref());
+    let kind = match found.kind {
+        Some(PreviousKind::Tracked) => "tracked (set through envision)",
+        Some(PreviousKind::Original) => "original (unchanged since the baseline)",
+        Some(PreviousKind::Untracked) => "untracked (changed outside envision)",
+        None => "not part of the session",
+    };
+    out.key_value("Category", kind);
+
+    let set_by = match (found.source, found.entry) {
+        (Some(source), _) => Some((source.describe(), source.at)),
+        (None, Some(entry)) => {
+            let by = match (&entry.profile, &entry.profile_path) {
+                (Some(name), Some(path)) => format!("profile {name} ({path})"),
+                (Some(name), None) => format!("profile {name}"),
+                _ => entry.command.clone(),
+            };
+            Some((by, entry.at))
+        }
+        (None, None) => None,
+    };
+    if let Some((by, at)) = set_by {
+        out.key_value("Set by", &by);
+        out.key_value("When", &output::format_timestamp(at));
+    }
+
+    if found.history.is_empty() {
+        out.info(&out.dim("No changes recorded in this session"));
+    } else {
+        out.info("History:");
+        for entry in &found.history {
+            out.info(&log::format_entry(out, entry));
+        }
+    }
+    Ok(0)
+}
+
+/// Classify `current` the way `unset` reports what it removed, looking
+/// further: a tracked variable changed again outside envision counts as
+/// untracked, and so does a baseline variable with a different value.
+pub fn provenance<'a>(session: &'a Session, var: &str, current: Option<&str>) -> Provenance<'a> {
+    let history: Vec<&JournalEntry> = session.journal.iter()
+        .filter(|e| e.var == var)
+        .collect();
+
+    let kind = match (session.tracked.get(var), current) {
+        (Some(TrackedChange::Set { value, .. }), Some(current)) if value == current => Some(PreviousKind::Tracked),
+        (Some(TrackedChange::Unset { .. }), None) => Some(PreviousKind::Tracked),
+        (Some(_), _) => Some(PreviousKind::Untracked),
+        (None, Some(current)) if session.in_baseline(var) && !session.baseline_changed(var, current) => {
+            Some(PreviousKind::Original)
+        }
+        (None, None) if !session.in_baseline(var) => None,
+        (None, _) => Some(PreviousKind::Untracked),
+    };
+
+    let (source, entry) = match kind {
+        Some(PreviousKind::Tracked) => (
+            session.tracked.get(var).and_then(TrackedChange::source),
+            history.iter().rev().find(|e| e.new.as_deref() == current).copied(),
+        ),
+        _ => (None, None),
+    };
+
+    Provenance { kind, source, entry, history }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SourceKind;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    fn session() -> Session {
+        let base: BTreeMap<String, String> = [("EDITOR", "vi"), ("HOME", "/root")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut session = Session::new(&base).unwrap();
+        session.record("set", None, [("EDITOR".into(), Some("vi".into()), Some("nano".into()))]);
+        session.track_set("EDITOR", "nano", None, SourceKind::Set);
+        session.record(
+            "profile",
+            Some(("dev", Path::new("/p/dev.sh"))),
+            [("EDITOR".into(), Some("nano".into()), Some("code".into()))],
+        );
+        session.track_set("EDITOR", "code", None, SourceKind::Profile {
+            name: "dev".into(),
+            path: "/p/dev.sh".into(),
+            checksum: "1".into(),
+        });
+        session
+    }
+
+    #[test]
+    fn tracked_value_names_its_source() {
+        let session = session();
+        let found = provenance(&session, "EDITOR", Some("code"));
+        assert!(matches!(found.kind, Some(PreviousKind::Tracked)));
+        assert_eq!(found.source.unwrap().describe(), "profile dev (/p/dev.sh)");
+        let entry = found.entry.unwrap();
+        assert_eq!(entry.profile.as_deref(), Some("dev"));
+        assert_eq!(entry.profile_path.as_deref(), Some("/p/dev.sh"));
+        assert_eq!(found.history.len(), 2);
+    }
+
+    #[test]
+    fn classifies_untouched_and_outside_changes() {
+        let session = session();
+        assert!(matches!(provenance(&session, "HOME", Some("/root")).kind, Some(PreviousKind::Original)));
+        assert!(matches!(provenance(&session, "HOME", Some("/tmp")).kind, Some(PreviousKind::Untracked)));
+        assert!(matches!(provenance(&session, "EDITOR", Some("vim")).kind, Some(PreviousKind::Untracked)));
+        assert!(provenance(&session, "EDITOR", Some("vim")).entry.is_none());
+        assert!(provenance(&session, "NOPE", None).kind.is_none());
+    }
+}
@@ -0,0 +1,57 @@
+use crate::export::Exports;
+use crate::journal::Change;
+use crate::output::Output;
+use crate::session::Session;
+
+/// Revert the last `count` operations.
+pub fn undo(out: &Output, ex: &mut Exports, count: usize) -> Result<u8, String> {
+    step(out, ex, count, "Undid", "Nothing to undo", Session::undo)
+}
+
+/// Reapply the last `count` undone operations.
+pub fn redo(out: &Output, ex: &mut Exports, count: usize) -> Result<u8, String> {
+    step(out, ex, count, "Redid", "Nothing to redo", Session::redo)
+}
+
+fn step(
+    out: &Output,
+    ex: &mut Exports,
+    count: usize,
+    done: &str,
+    nothing: &str,
+    apply: fn(&mut Session) -> Option<Vec<Change>>,
+) -> Result<u8, String> {
+    let mut session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+
+    let mut steps = 0;
+    while steps < count {
+        let Some(changes) = apply(&mut session) else { break };
+        for (var, _, value) in &changes {
+            match value {
+                Some(value) => {
+                    ex.set_var(var, value);
+                    out.info(&format!("  set {var}={value}"));
+                }
+                None => {
+                    ex.unset_var(var);
+                    out.info(&format!("  unset {var}"));
+                }
+            }
+        }
+        steps += 1;
+    }
+
+    if steps == 0 {
+        out.success(nothing);
+        return Ok(0);
+    }
+
+    ex.save_session(&session)?;
+
+    out.success(&format!("{done} {steps} operation(s)"));
+    if steps < count {
+        out.warn(&format!("Only {steps} of {count} operation(s) available"));
+    }
+    Ok(0)
+}
@@ -12,11 +12,22 @@ pub fn run(shell: &Shell) -> Result<u8, String> {
 
 /// Shared bash/zsh: envision wrapper + fixed-top banner using scroll regions.
 const COMMON_HOOK: &str = r#"
+# First argument after the global options: the subcommand.
+_envision_subcommand() {
+    while [ $# -gt 0 ]; do
+        case "$1" in
+            --format|--json-fd) [ $# -ge 2 ] && shift; shift ;;
+            -*) shift ;;
+            *) printf '%s' "$1"; return ;;
+        esac
+    done
+}
+
 envision() {
-    case "$1" in
-        session|set|unset|clear|profile)
+    case "$(_envision_subcommand "$@")" in
+        session|set|unset|clear|profile|snapshot|undo|redo|adopt)
             local _envision_out
-            _envision_out="$(command envision "$@")"
+            _envision_out="$(ENVISION_SHELL_PID=$$ command envision "$@")"
             local _envision_rc=$?
             if [ $_envision_rc -eq 0 ] && [ -n "$_envision_out" ]; then
                 eval "$_envision_out"
@@ -24,7 +35,7 @@ envision() {
             return $_envision_rc
             ;;
         *)
-            command envision "$@"
+            ENVISION_SHELL_PID=$$ command envision "$@"
             ;;
     esac
 }
@@ -57,7 +68,8 @@ _envision_banner() {
         local _state="clean"
         [ "${ENVISION_DIRTY}" = "1" ] && _state="dirty"
         local _tracked="${ENVISION_TRACKED:-0}"
-        local _sess="${ENVISION_SESSION_ID} | ${_tracked} tracked | ${_state}"
+        local _label="${ENVISION_SESSION_NAME:-${ENVISION_SESSION_ID}}"
+        local _sess="${_label} | ${_tracked} tracked | ${_state}"
         if [ -n "${_parts}" ]; then
             _parts="${_parts} | ${_sess}"
         else
@@ -104,17 +116,36 @@ fi
 "#;
 
 const FISH_HOOK: &str = r#"
+# First argument after the global options: the subcommand.
+function __envision_subcommand
+    set -l skip 0
+    for arg in $argv
+        if test $skip -eq 1
+            set skip 0
+            continue
+        end
+        switch $arg
+            case --format --json-fd
+                set skip 1
+            case '-*'
+            case '*'
+                echo $arg
+                return
+        end
+    end
+end
+
 function envision
-    switch $argv[1]
-        case session set unset clear profile
-            set -l _envision_out (command envision $argv)
+    switch (__envision_subcommand $argv)
+        case session set unset clear profile snapshot undo redo adopt
+            set -l _envision_out (ENVISION_SHELL_PID=$fish_pid command envision $argv)
             set -l _envision_rc $status
             if test $_envision_rc -eq 0; and test -n "$_envision_out"
                 eval $_envision_out
             end
             return $_envision_rc
         case '*'
-            command envision $argv
+            ENVISION_SHELL_PID=$fish_pid command envision $argv
     end
 end
 
@@ -137,7 +168,9 @@ function _envision_banner --on-event fish_prompt
         set -l _state "clean"
         test "$ENVISION_DIRTY" = "1"; and set _state "dirty"
         set -l _tracked (test -n "$ENVISION_TRACKED"; and echo $ENVISION_TRACKED; or echo 0)
-        set -l _sess "$ENVISION_SESSION_ID | $_tracked tracked | $_state"
+        set -l _label $ENVISION_SESSION_ID
+        test -n "$ENVISION_SESSION_NAME"; and set _label $ENVISION_SESSION_NAME
+        set -l _sess "$_label | $_tracked tracked | $_state"
         if test -n "$_parts"
             set _parts "$_parts | $_sess"
         else
@@ -0,0 +1,57 @@
+use crate::journal::JournalEntry;
+use crate::output::{self, Output};
+use crate::session::Session;
+
+/// Show the session journal, oldest first, optionally filtered.
+pub fn run(
+    out: &Output,
+    var: Option<&str>,
+    profile: Option<&str>,
+    limit: Option<usize>,
+) -> Result<u8, String> {
+    let session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+
+    let entries: Vec<&JournalEntry> = session.journal.iter()
+        .filter(|e| e.matches(var, profile))
+        .collect();
+
+    if entries.is_empty() {
+        out.info("No changes recorded");
+        return Ok(0);
+    }
+
+    let skip = limit.map_or(0, |n| entries.len().saturating_sub(n));
+    for entry in &entries[skip..] {
+        out.info(&format_entry(out, entry));
+    }
+
+    Ok(0)
+}
+
+pub fn format_entry(out: &Output, entry: &JournalEntry) -> String {
+    let command = match &entry.profile {
+        Some(profile) => format!("{} {profile}", entry.command),
+        None => entry.command.clone(),
+    };
+    // Operations such as adopt record a variable without changing it
+    let change = if entry.old == entry.new {
+        format!("{} {}", describe(out, entry.new.as_deref()), out.dim("(unchanged)"))
+    } else {
+        format!("{} -> {}", describe(out, entry.old.as_deref()), describe(out, entry.new.as_deref()))
+    };
+    format!(
+        "  {}  {}  {}  {}: {change}",
+        out.dim(&format!("#{}", entry.op)),
+        output::format_timestamp(entry.at),
+        command,
+        out.bold(&entry.var),
+    )
+}
+
+fn describe(out: &Output, value: Option<&str>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => out.dim("(unset)"),
+    }
+}
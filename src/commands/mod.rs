@@ -1,8 +1,15 @@
+pub mod adopt;
 pub mod banner;
 pub mod clear;
+pub mod diff;
+pub mod get;
+pub mod history;
 pub mod hook;
+pub mod log;
 pub mod profile;
 pub mod session;
 pub mod set;
+pub mod snapshot;
 pub mod status;
+pub mod transfer;
 pub mod unset;
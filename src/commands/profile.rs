@@ -1,12 +1,15 @@
-use crate::export::{self, Exports};
+use crate::commands::snapshot;
+use crate::export::Exports;
 use crate::output::Output;
-use crate::session::{hash_value, SESSION_VAR};
+use crate::pathlist;
+use crate::session::{self, SourceKind, hash_value};
+use serde_json::{Value, json};
 use std::collections::BTreeMap;
 use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 
-const PROFILE_VAR: &str = "ENVISION_PROFILE";
-const CHECKSUM_VAR: &str = "ENVISION_PROFILE_CHECKSUM";
+pub const PROFILE_VAR: &str = "ENVISION_PROFILE";
+pub const CHECKSUM_VAR: &str = "ENVISION_PROFILE_CHECKSUM";
 
 /// Variables that inherently differ in a bash subshell — not real changes.
 const SUBSHELL_NOISE: &[&str] = &["_", "SHLVL", "BASH_EXECUTION_STRING"];
@@ -26,6 +29,9 @@ pub fn run(out: &Output, ex: &mut Exports, path: &str, yes: bool, dry_run: bool)
 
     // 08-R6, 08-R7: confirmation prompt on first load (no checksum stored)
     if !yes && std::env::var(CHECKSUM_VAR).is_err() {
+        if out.is_json() {
+            return Err("Confirmation required: use --yes with --format json".into());
+        }
         prompt_confirmation(out, &path)?;
     }
 
@@ -40,6 +46,10 @@ pub fn run(out: &Output, ex: &mut Exports, path: &str, yes: bool, dry_run: bool)
 
     // Compute diff, filtering noise
     let changes = compute_diff(&before, &after);
+    out.field("profile", resolve_profile_name(&path));
+    out.field("path", path.display().to_string());
+    out.field("dry_run", dry_run);
+    out.field("changes", changes_json(&changes, &before));
 
     // 08-R22, 08-R23: dry-run mode
     if dry_run {
@@ -50,13 +60,26 @@ pub fn run(out: &Output, ex: &mut Exports, path: &str, yes: bool, dry_run: bool)
         }
         for change in &changes {
             match change {
-                EnvChange::Set(var, value) => out.info(&format!("  set {var}={value}")),
+                EnvChange::Set(var, value) => {
+                    let entries = before.get(var)
+                        .and_then(|old| pathlist::describe_change(var, old, value));
+                    match entries {
+                        Some(entries) => out.info(&format!("  set {var}: {entries}")),
+                        None => out.info(&format!("  set {var}={value}")),
+                    }
+                }
                 EnvChange::Unset(var) => out.info(&format!("  unset {var}")),
             }
         }
         return Ok(0);
     }
 
+    // Snapshot before applying so the previous environment can be restored
+    let profile_name = resolve_profile_name(&path);
+    if let Some(sess) = sess.as_mut() {
+        snapshot::auto(sess, &format!("before profile {profile_name}"));
+    }
+
     // Apply changes via Exports
     for change in &changes {
         match change {
@@ -66,7 +89,6 @@ pub fn run(out: &Output, ex: &mut Exports, path: &str, yes: bool, dry_run: bool)
     }
 
     // 08-R8, 08-R11: set ENVISION_PROFILE
-    let profile_name = resolve_profile_name(&path);
     ex.set_var(PROFILE_VAR, &profile_name);
 
     // 08-R24: compute and store file checksum
@@ -75,14 +97,38 @@ pub fn run(out: &Output, ex: &mut Exports, path: &str, yes: bool, dry_run: bool)
     let checksum = hash_value(&contents);
     ex.set_var(CHECKSUM_VAR, &checksum.to_string());
 
-    // 08-R20: track all changes in the active session
-    for change in &changes {
-        match change {
-            EnvChange::Set(var, value) => { sess.track_set(var, value); }
-            EnvChange::Unset(var) => { sess.track_unset(var); }
+    if let Some(mut sess) = sess {
+        // Journal before tracking so undo can reinstate the prior tracked state.
+        // The profile marker vars are included so undo also drops the profile name.
+        let markers = [(PROFILE_VAR, profile_name.clone()), (CHECKSUM_VAR, checksum.to_string())];
+        let journaled = changes.iter()
+            .map(|change| match change {
+                EnvChange::Set(var, value) => (var.clone(), before.get(var).cloned(), Some(value.clone())),
+                EnvChange::Unset(var) => (var.clone(), before.get(var).cloned(), None),
+            })
+            .chain(markers.into_iter()
+                .filter(|(var, value)| before.get(*var) != Some(value))
+                .map(|(var, value)| (var.to_string(), before.get(var).cloned(), Some(value))));
+        sess.record("profile", Some((&profile_name, &path)), journaled);
+
+        // 08-R20: track all changes in the active session
+        let source = SourceKind::Profile {
+            name: profile_name.clone(),
+            path: path.display().to_string(),
+            checksum: checksum.to_string(),
+        };
+        for change in &changes {
+            match change {
+                EnvChange::Set(var, value) => {
+                    sess.track_set(var, value, before.get(var).map(String::as_str), source.clone());
+                }
+                EnvChange::Unset(var) => {
+                    sess.track_unset(var, before.get(var).map(String::as_str), source.clone());
+                }
+            }
         }
+        ex.save_session(&sess)?;
     }
-    ex.save_session(&sess)?;
 
     // 08-R21: display confirmation
     out.success(&format!("Profile '{profile_name}' loaded"));
@@ -91,7 +137,26 @@ pub fn run(out: &Output, ex: &mut Exports, path: &str, yes: bool, dry_run: bool)
     Ok(0)
 }
 
-enum EnvChange {
+/// The profile's changes as JSON, with sensitive values redacted.
+fn changes_json(changes: &[EnvChange], before: &BTreeMap<String, String>) -> Vec<Value> {
+    let previous = |var: &str| before.get(var).map(|p| session::redact(var, p));
+    changes.iter()
+        .map(|change| match change {
+            EnvChange::Set(var, value) => json!({
+                "var": var,
+                "action": "set",
+                "value": session::redact(var, value),
+                "previous": previous(var),
+                "entries": before.get(var)
+                    .filter(|_| !session::is_sensitive_var(var))
+                    .and_then(|old| pathlist::describe_change(var, old, value)),
+            }),
+            EnvChange::Unset(var) => json!({ "var": var, "action": "unset", "previous": previous(var) }),
+        })
+        .collect()
+}
+
+pub enum EnvChange {
     Set(String, String),
     Unset(String),
 }
@@ -187,8 +252,10 @@ fn execute_profile(path: &Path) -> Result<BTreeMap<String, String>, String> {
     Ok(env)
 }
 
-/// Compute the diff between before and after environments.
-fn compute_diff(
+/// Compute the diff between before and after environments. Every
+/// assignment the profile makes is applied; ignore rules only affect
+/// change detection.
+pub fn compute_diff(
     before: &BTreeMap<String, String>,
     after: &BTreeMap<String, String>,
 ) -> Vec<EnvChange> {
@@ -218,24 +285,18 @@ fn compute_diff(
     changes
 }
 
-/// Skip variables that are subshell noise or managed by envision.
+/// Skip subshell noise and variables managed by envision.
 fn should_skip(var: &str) -> bool {
-    SUBSHELL_NOISE.contains(&var)
-        || var == SESSION_VAR
-        || var == PROFILE_VAR
-        || var == CHECKSUM_VAR
-        || var == export::SESSION_ID_VAR
-        || var == export::TRACKED_COUNT_VAR
-        || var == export::DIRTY_VAR
+    SUBSHELL_NOISE.contains(&var) || session::is_envision_var(var)
 }
 
 /// 08-R11: derive profile name from filename (strip extension).
 fn resolve_profile_name(path: &Path) -> String {
     // 08-R8: use existing ENVISION_PROFILE if set
-    if let Ok(existing) = std::env::var(PROFILE_VAR) {
-        if !existing.is_empty() {
-            return existing;
-        }
+    if let Ok(existing) = std::env::var(PROFILE_VAR)
+        && !existing.is_empty()
+    {
+        return existing;
     }
 
     let name = path.file_name()
@@ -287,6 +348,17 @@ mod tests {
         assert_eq!(resolve_profile_name(path), "production");
     }
 
+    #[test]
+    fn json_redacts_sensitive_values() {
+        let before = BTreeMap::from([("DB_PASSWORD".to_string(), "old".to_string())]);
+        let changes = [EnvChange::Set("DB_PASSWORD".into(), "new".into()), EnvChange::Unset("API_KEY".into())];
+        let json = changes_json(&changes, &before);
+        assert_eq!(json[0]["value"], session::REDACTED);
+        assert_eq!(json[0]["previous"], session::REDACTED);
+        assert!(json[0]["entries"].is_null());
+        assert!(!serde_json::to_string(&json).unwrap().contains("old"));
+    }
+
     #[test]
     fn resolve_relative_path() {
         let cwd = std::env::current_dir().unwrap();
@@ -352,9 +424,20 @@ mod tests {
         after.insert("_".into(), "/usr/bin/env".into());
         after.insert("SHLVL".into(), "2".into());
         after.insert("BASH_EXECUTION_STRING".into(), "source ...".into());
-        after.insert(SESSION_VAR.into(), "data".into());
+        after.insert(session::SESSION_VAR.into(), "data".into());
 
         let changes = compute_diff(&before, &after);
         assert!(changes.is_empty());
     }
+
+    #[test]
+    fn diff_keeps_vars_users_ignore() {
+        let before = BTreeMap::from([("PWD".to_string(), "/home".to_string())]);
+        let after = BTreeMap::from([
+            ("PWD".to_string(), "/home".to_string()),
+            ("PS1".to_string(), "$ ".to_string()),
+            ("STARSHIP_SHELL".to_string(), "bash".to_string()),
+        ]);
+        assert_eq!(compute_diff(&before, &after).len(), 2);
+    }
 }
@@ -1,15 +1,74 @@
+use crate::commands::profile::PROFILE_VAR;
+use crate::config::Config;
 use crate::export::Exports;
-use crate::output::Output;
-use crate::session::Session;
-use std::collections::BTreeMap;
+use crate::fingerprint::{self, Scheme};
+use crate::ignore::Ignore;
+use crate::output::{self, Output};
+use crate::owner::{Owner, OwnerStatus};
+use crate::session::{self, Session};
+use crate::store::{self, Storage};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
 
-pub fn init(out: &Output, ex: &mut Exports, force: bool, resume: bool) -> Result<u8, String> {
-    let existing = Session::load()?;
+/// Flags of `session init`.
+pub struct InitOptions {
+    pub force: bool,
+    pub resume: bool,
+    pub full_baseline: bool,
+    pub storage: Option<Storage>,
+    pub child: bool,
+    pub name: Option<String>,
+}
+
+pub fn init(out: &Output, ex: &mut Exports, opts: InitOptions) -> Result<u8, String> {
+    let InitOptions { force, resume, full_baseline, storage, child, name } = opts;
+    if let Some(name) = &name {
+        session::validate_session_name(name)?;
+    }
+
+    // 01-R8: --force starts over even if the old session can't be read
+    let existing = match Session::load() {
+        Ok(existing) => existing,
+        Err(e) if force => {
+            out.warn(&format!("Discarding unreadable session: {e}"));
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Nest a new session under the one inherited from the parent shell
+    if child {
+        let parent = existing
+            .ok_or("No session to nest under. Run 'envision session init' first.")?;
+        if let Some(problem) = &parent.baseline_problem {
+            return Err(format!("Cannot nest under session {}: {problem}", parent.id));
+        }
+        let env: BTreeMap<String, String> = std::env::vars().collect();
+        let mut session = Session::new_child(&parent, &env)?;
+        session.name = name;
+        if let Some(storage) = storage {
+            session.storage = storage;
+        }
+        ex.save_session(&session)?;
+
+        out.success("Child session initialized");
+        report_new_session(out, &session);
+        return Ok(0);
+    }
+
+    // 01-R12: say so when the existing session belongs to another shell
+    if let Some(warning) = existing.as_ref().and_then(owner_warning)
+        && !force
+    {
+        out.warn(&warning);
+    }
 
     // 01-R9: --resume continues existing session
     if resume {
         match existing {
             Some(session) => {
+                out.field("session", session_json(&session));
+                out.field("resumed", true);
                 out.success("Session resumed");
                 out.key_value("Session", &session.id);
                 return Ok(0);
@@ -28,7 +87,7 @@ pub fn init(out: &Output, ex: &mut Exports, force: bool, resume: bool) -> Result
     }
 
     // 01-R8: --force warns and reinitializes
-    if existing.is_some() && force {
+    if existing.is_some() {
         out.warn("Reinitializing session (previous tracking history will be lost)");
     }
 
@@ -38,30 +97,510 @@ pub fn init(out: &Output, ex: &mut Exports, force: bool, resume: bool) -> Result
     // 01-R3: generate unique session identifier
     // 01-R5: initialize empty tracking state
     // 01-R6: record timestamp
-    let session = Session::new(&env);
+    let config = Config::from_env();
+    let mut session = create_session(&env, full_baseline || config.full_baseline)?;
+    session.name = name;
+    session.storage = storage.unwrap_or(config.storage);
     ex.save_session(&session)?;
 
+    // The old file goes once the new session is written, and only if no
+    // other shell may still be using it
+    if let Some(old) = &existing {
+        release_storage(ex, old);
+    }
+
     // 01-R10: display results (to stderr)
     // 01-R13: banner is activated via update_banner_vars() in main.rs
     out.success("Session initialized");
+    report_new_session(out, &session);
+
+    // 01-R12: offer to clean up after crashed shells
+    let stale = stale_sessions(&session.id);
+    out.field("stale_sessions", stale.len());
+    if !stale.is_empty() {
+        out.info(&format!(
+            "Found {} session file(s) left by shells that have exited. \
+             Run 'envision session prune' to remove them.",
+            stale.len()
+        ));
+    }
+
+    Ok(0)
+}
+
+/// Accept untracked changes into the baseline. Tracked changes are kept.
+pub fn rebase(out: &Output, ex: &mut Exports) -> Result<u8, String> {
+    let mut session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+    let absorbed = session::list_untracked(&session, &env, &Ignore::load()).len();
+    session.rebase(&env);
+    ex.save_session(&session)?;
+
+    out.success("Baseline re-captured");
+    out.key_value("Captured", &format!("{} variables", session.baseline.len()));
+    out.key_value("Accepted", &format!("{absorbed} untracked change(s)"));
+    out.key_value("Tracked", &session.tracked.len().to_string());
+    Ok(0)
+}
+
+/// 01-R12: give this shell its own copy of an inherited session, so the
+/// two shells stop sharing one history (and one file, with file storage).
+pub fn fork(out: &Output, ex: &mut Exports) -> Result<u8, String> {
+    let mut session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+
+    let parent = std::mem::replace(
+        &mut session.id,
+        session::generate_session_id()?,
+    );
+    session.owner = Some(Owner::current());
+    ex.save_session(&session)?;
+
+    out.success("Session forked");
+    out.key_value("Session", &session.id);
+    out.key_value("Forked from", &parent);
+    out.key_value("Storage", &session.location());
+    Ok(0)
+}
+
+/// 01-R12: make this shell the owner of an inherited or abandoned session.
+pub fn claim(out: &Output, ex: &mut Exports) -> Result<u8, String> {
+    let mut session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+
+    let current = Owner::current();
+    if let Some(previous) = session.owner.replace(current.clone())
+        && previous.status(&current) == OwnerStatus::Foreign
+        && session.storage == Storage::File
+    {
+        out.warn(&format!(
+            "The shell with {} still shares this session file. \
+             Use 'envision session fork' to keep the shells apart.",
+            previous.describe()
+        ));
+    }
+    ex.save_session(&session)?;
+
+    out.success("Session claimed");
     out.key_value("Session", &session.id);
+    out.key_value("Shell", &current.describe());
+    Ok(0)
+}
+
+/// End tracking: optionally revert tracked changes, then remove the session
+/// and every envision-managed variable from the shell.
+pub fn end(out: &Output, ex: &mut Exports, revert: bool, keep: bool) -> Result<u8, String> {
+    let session = match Session::read() {
+        Ok(Some(session)) => session,
+        Ok(None) => return Err("No active session. Run 'envision session init' first.".into()),
+        // Damaged data can still be torn down, just not reverted or
+        // summarized. Its owner can't be read, so the file is left alone.
+        Err(damage) if !revert => {
+            out.warn(&damage.problem);
+            if let Some(id) = &damage.file_id {
+                out.info(&format!(
+                    "The session file {} was kept in case another shell uses it; delete it once none does.",
+                    store::session_path(id)?.display()
+                ));
+            }
+            remove_envision_vars(ex, &std::env::vars().collect());
+            out.success("Session ended");
+            return Ok(0);
+        }
+        Err(damage) => return Err(damage.message()),
+    };
+
+    if !session.tracked.is_empty() && !revert && !keep {
+        return Err(format!(
+            "Session has {} tracked change(s). Use --revert to undo them or --keep to leave them applied.",
+            session.tracked.len()
+        ));
+    }
+
+    let (to_unset, to_restore) = revert_statements(ex, &session, revert);
+    release_storage(ex, &session);
+    remove_envision_vars(ex, &std::env::vars().collect());
+
+    out.success(&format!("Session {} ended", session.id));
+    out.key_value("Duration", &output::format_duration(session::now_secs().saturating_sub(session.created_at)));
+    let operations = session.journal.iter()
+        .filter(|e| e.command != "undo" && e.command != "redo")
+        .map(|e| e.op)
+        .collect::<BTreeSet<_>>();
+    let variables = session.journal.iter()
+        .map(|e| &e.var)
+        .filter(|var| !var.starts_with(PROFILE_VAR))
+        .collect::<BTreeSet<_>>();
+    out.key_value("Operations", &operations.len().to_string());
+    out.key_value("Variables changed", &variables.len().to_string());
+    if revert {
+        out.key_value("Reverted", &format!("{} removed, {} restored", to_unset.len(), to_restore.len()));
+    } else if !session.tracked.is_empty() {
+        out.key_value("Kept", &format!("{} tracked change(s)", session.tracked.len()));
+    }
+    Ok(0)
+}
+
+/// Queue the statements that undo the session's tracked changes, if
+/// `revert` is set. Returns the variables removed and restored.
+fn revert_statements(
+    ex: &mut Exports,
+    session: &Session,
+    revert: bool,
+) -> (Vec<String>, Vec<(String, String)>) {
+    if !revert {
+        return (Vec::new(), Vec::new());
+    }
+    let (to_unset, to_restore) = crate::commands::clear::preview_changes(session);
+    for var in &to_unset {
+        ex.unset_var(var);
+    }
+    for (var, value) in &to_restore {
+        ex.set_var(var, value);
+    }
+    (to_unset, to_restore)
+}
+
+/// 01-R12: stop tracking in this shell. Variables keep their values. The
+/// session file is kept while the shell that owns it is still running.
+pub fn discard(out: &Output, ex: &mut Exports) -> Result<u8, String> {
+    let session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
 
+    release_storage(ex, &session);
+    ex.end_session();
+
+    out.success(&format!("Stopped tracking session {}", session.id));
+    if !session.tracked.is_empty() {
+        out.info(&format!("{} tracked change(s) stay applied in this shell", session.tracked.len()));
+    }
     Ok(0)
 }
 
-/// Ensure a session exists, creating one if needed. Returns the active session.
+/// Queue deletion of the session file unless another running shell still
+/// owns it, or might: a file whose owner is unknown is left alone.
+fn release_storage(ex: &mut Exports, session: &Session) {
+    if should_release(session, &Owner::current()) {
+        ex.remove_file(&session.id);
+    }
+}
+
+/// Whether ending the session in the `current` shell may delete its file.
+fn should_release(session: &Session, current: &Owner) -> bool {
+    let owner_running = session.owner.as_ref().is_some_and(|owner| {
+        matches!(owner.status(current), OwnerStatus::Foreign | OwnerStatus::Unknown)
+    });
+    session.storage == Storage::File && !owner_running
+}
+
+/// Queue removal of the session and of the profile marker variables found
+/// in `env`. The banner variables are removed by update_banner_vars() once
+/// the session is gone.
+fn remove_envision_vars(ex: &mut Exports, env: &BTreeMap<String, String>) {
+    ex.end_session();
+    for var in env.keys() {
+        if var.starts_with(PROFILE_VAR) {
+            ex.unset_var(var);
+        }
+    }
+}
+
+/// 01-R12: remove session files whose shells have exited.
+pub fn prune(out: &Output) -> Result<u8, String> {
+    let keep = Session::read().ok().flatten().map(|s| s.id).unwrap_or_default();
+    let stale = stale_sessions(&keep);
+    for id in &stale {
+        store::remove(id)?;
+    }
+
+    if stale.is_empty() {
+        out.success("No stale session files");
+    } else {
+        out.success(&format!("Removed {} stale session file(s)", stale.len()));
+    }
+    Ok(0)
+}
+
+/// 01-R12: warn when the session was inherited from another shell or left
+/// behind by one that exited, or when its baseline cannot be compared.
+pub fn warn_session_problems(out: &Output) {
+    let Ok(Some(session)) = Session::read() else { return };
+    if let Some(problem) = &session.baseline_problem {
+        out.warn(&baseline_warning(problem));
+    }
+    if let Some(warning) = owner_warning(&session) {
+        out.warn(&warning);
+    }
+}
+
+/// What a baseline that cannot be compared means, and how to fix it.
+pub fn baseline_warning(problem: &str) -> String {
+    format!(
+        "{problem}. Values changed outside envision go unnoticed until 'envision session rebase' \
+         starts a new baseline; tracked changes, undo and clear still work."
+    )
+}
+
+/// Describe a session owned by another shell, with the ways to resolve it.
+fn owner_warning(session: &Session) -> Option<String> {
+    let owner = session.owner.as_ref()?;
+    let problem = match owner.status(&Owner::current()) {
+        // Without PIDs there is nothing reliable to warn about
+        OwnerStatus::Current | OwnerStatus::Unknown => return None,
+        OwnerStatus::Foreign => format!(
+            "Session {} belongs to another shell ({})",
+            session.id,
+            owner.describe()
+        ),
+        OwnerStatus::Dead => format!(
+            "Session {} was started by a shell that has exited ({})",
+            session.id,
+            owner.describe()
+        ),
+    };
+    Some(format!(
+        "{problem}. Run 'envision session init --child' to track this shell on top of it, \
+         'envision session fork' to copy it, 'envision session claim' to take it over, \
+         or 'envision session discard' to stop tracking here."
+    ))
+}
+
+/// IDs of session files owned by shells on this host that have exited.
+/// Unreadable files are left alone: their owner cannot be known.
+fn stale_sessions(keep: &str) -> Vec<String> {
+    let current = Owner::current();
+    store::list()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|id| id != keep)
+        .filter(|id| {
+            store::read(id)
+                .ok()
+                .and_then(|data| Session::decode(&data).ok())
+                .and_then(|session| session.owner)
+                .is_some_and(|owner| owner.status(&current) == OwnerStatus::Dead)
+        })
+        .collect()
+}
+
+/// Replace a damaged session with a fresh one built from the current
+/// environment, carrying over whatever could be salvaged. 00-R9
+pub fn repair(out: &Output, ex: &mut Exports) -> Result<u8, String> {
+    let damage = match Session::read() {
+        Ok(Some(_)) => {
+            out.success("Session is intact, nothing to repair");
+            return Ok(0);
+        }
+        Ok(None) => return Err("No active session. Run 'envision session init' first.".into()),
+        Err(damage) => damage,
+    };
+    out.warn(&damage.problem);
+
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+    let config = Config::from_env();
+    let mut session = create_session(&env, config.full_baseline)?;
+    session.storage = if damage.file_id.is_some() { Storage::File } else { config.storage };
+
+    let salvaged = damage.salvaged;
+    let mut lost = salvaged.lost();
+    lost.push("undo history");
+    let mut recovered = Vec::new();
+    if let Some(tracked) = salvaged.tracked {
+        recovered.push(format!("{} tracked change(s)", tracked.len()));
+        session.tracked = tracked;
+    }
+    if let Some(snapshots) = salvaged.snapshots {
+        recovered.push(format!("{} snapshot(s)", snapshots.len()));
+        session.snapshot_seq = snapshots.iter().map(|s| s.seq).max().unwrap_or(0);
+        session.snapshots = snapshots;
+    }
+    if let Some(journal) = salvaged.journal {
+        recovered.push(format!("{} log entries", journal.len()));
+        session.op_seq = journal.iter().map(|e| e.op).max().unwrap_or(0);
+        session.journal = journal;
+    }
+    ex.save_session(&session)?;
+
+    // The damaged file is superseded once the new session is written
+    if let Some(old_id) = &damage.file_id
+        && *old_id != session.id
+    {
+        ex.remove_file(old_id);
+    }
+
+    out.success("Session repaired");
+    out.key_value("Session", &session.id);
+    if recovered.is_empty() {
+        out.key_value("Recovered", "nothing");
+    } else {
+        out.key_value("Recovered", &recovered.join(", "));
+    }
+    out.key_value("Lost", &lost.join(", "));
+    out.key_value("Baseline", "re-captured from the current environment");
+    out.key_value("Storage", &session.location());
+    Ok(0)
+}
+
+/// Load the session for a command that works without tracking. Damaged
+/// data is reported and skipped so the change itself still applies. 00-R9
+pub fn load_for_tracking(out: &Output) -> Option<Session> {
+    match Session::read() {
+        Ok(session) => session,
+        Err(damage) => {
+            out.warn(&format!("Not tracked: {}", damage.message()));
+            None
+        }
+    }
+}
+
+/// Ensure a session exists, creating one if needed. Returns the active
+/// session, or `None` if the existing one is damaged: it is reported, never
+/// silently replaced, and the caller proceeds without tracking (00-R9).
 /// Used by profile (08-R1) and any command that requires an active session.
-pub fn ensure_session(out: &Output, ex: &mut Exports) -> Result<Session, String> {
-    if let Some(session) = Session::load()? {
-        return Ok(session);
+pub fn ensure_session(out: &Output, ex: &mut Exports) -> Result<Option<Session>, String> {
+    match Session::read() {
+        Ok(Some(session)) => return Ok(Some(session)),
+        Ok(None) => {}
+        Err(damage) => {
+            out.warn(&format!("Not tracked: {}", damage.message()));
+            return Ok(None);
+        }
     }
 
     let env: BTreeMap<String, String> = std::env::vars().collect();
-    let session = Session::new(&env);
+    let config = Config::from_env();
+    let mut session = create_session(&env, config.full_baseline)?;
+    session.storage = config.storage;
     ex.save_session(&session)?;
 
     out.success("Session initialized");
-    out.key_value("Session", &session.id);
+    report_new_session(out, &session);
+
+    Ok(Some(session))
+}
 
+/// Build a new session, optionally keeping full original values.
+fn create_session(env: &BTreeMap<String, String>, full_baseline: bool) -> Result<Session, String> {
+    let mut session = Session::new(env)?;
+    if full_baseline {
+        session.capture_originals(env);
+    }
     Ok(session)
 }
+
+/// Identity of a session in JSON documents.
+fn session_json(session: &Session) -> Value {
+    json!({
+        "id": session.id,
+        "name": session.name,
+        "parent": session.parent.as_ref().map(|l| &l.parent_id),
+        "storage": session.location(),
+    })
+}
+
+/// 01-R10: describe the new session, its baseline and where it is stored.
+fn report_new_session(out: &Output, session: &Session) {
+    out.field("session", session_json(session));
+    out.field("resumed", false);
+    out.field("captured", session.baseline.len());
+    out.field("full_baseline", session.originals.is_some());
+    out.key_value("Session", &session.id);
+    if let Some(name) = &session.name {
+        out.key_value("Name", name);
+    }
+    if let Some(lineage) = &session.parent {
+        out.key_value("Parent", &lineage.parent_id);
+    }
+    out.key_value("Captured", &format!("{} variables", session.baseline.len()));
+    if let Some(originals) = &session.originals {
+        let hashed_only = session.baseline.len() - originals.len();
+        out.key_value("Baseline", "full values kept");
+        if hashed_only > 0 {
+            out.key_value("Sensitive", &format!("{hashed_only} variable(s) kept as hash only"));
+        }
+    }
+    out.key_value("Storage", &session.location());
+    if session.fingerprint == Scheme::Fnv1a
+        && let Err(e) = fingerprint::secret()
+    {
+        out.warn(&format!("Baseline values are hashed without a key: {e}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SourceKind;
+
+    fn session() -> Session {
+        let mut session = Session::new(&BTreeMap::new()).unwrap();
+        session.track_set("NEW", "1", None, SourceKind::Set);
+        session.tracked.insert("EDIT".into(), session::TrackedChange::Set {
+            value: "new".into(),
+            previous: Some("old".into()),
+            source: None,
+        });
+        session
+    }
+
+    fn owner(pid: u32) -> Owner {
+        Owner { pid: Some(pid), tty: None, host: Some("box".into()) }
+    }
+
+    #[test]
+    fn end_with_revert_undoes_tracked_changes() {
+        let mut ex = Exports::new();
+        let (to_unset, to_restore) = revert_statements(&mut ex, &session(), true);
+        assert_eq!(to_unset, ["NEW"]);
+        assert_eq!(to_restore, [("EDIT".to_string(), "old".to_string())]);
+        assert_eq!(ex.statements(), ["unset NEW", "export EDIT='old'"]);
+    }
+
+    #[test]
+    fn end_with_keep_leaves_tracked_changes() {
+        let mut ex = Exports::new();
+        let (to_unset, to_restore) = revert_statements(&mut ex, &session(), false);
+        assert!(to_unset.is_empty() && to_restore.is_empty());
+        assert!(ex.statements().is_empty());
+    }
+
+    #[test]
+    fn removes_session_and_profile_markers() {
+        let env = BTreeMap::from([
+            (PROFILE_VAR.to_string(), "dev".to_string()),
+            (format!("{PROFILE_VAR}_CHECKSUM"), "abc".to_string()),
+            ("EDITOR".to_string(), "vi".to_string()),
+        ]);
+        let mut ex = Exports::new();
+        remove_envision_vars(&mut ex, &env);
+        assert_eq!(ex.statements(), [
+            format!("unset {}", session::SESSION_VAR),
+            format!("unset {PROFILE_VAR}"),
+            format!("unset {PROFILE_VAR}_CHECKSUM"),
+        ]);
+    }
+
+    #[test]
+    fn releases_only_files_no_other_shell_may_use() {
+        let mut session = session();
+        session.storage = Storage::File;
+        session.owner = Some(owner(u32::MAX));
+
+        // The owner has exited, or is this shell
+        assert!(should_release(&session, &owner(1)));
+        session.owner = Some(owner(1));
+        assert!(should_release(&session, &owner(1)));
+
+        // This shell's PID or the owner's is unknown
+        assert!(!should_release(&session, &Owner { pid: None, ..owner(1) }));
+        session.owner = Some(Owner { pid: None, ..owner(1) });
+        assert!(!should_release(&session, &owner(1)));
+
+        // Environment storage has no file to delete
+        session.owner = Some(owner(1));
+        session.storage = Storage::Env;
+        assert!(!should_release(&session, &owner(1)));
+    }
+}
@@ -1,6 +1,7 @@
+use crate::commands::snapshot;
 use crate::export::Exports;
 use crate::output::Output;
-use crate::session::{self, OverwriteKind, Session, TrackedChange};
+use crate::session::{self, OverwriteKind, SourceKind, TrackedChange};
 
 pub fn run(out: &Output, ex: &mut Exports, var: &str, value: &str) -> Result<u8, String> {
     // 03-R2, 03-R3: validate POSIX variable name
@@ -16,30 +17,58 @@ pub fn run(out: &Output, ex: &mut Exports, var: &str, value: &str) -> Result<u8,
 
     // 03-R10: confirm the variable was set (to stderr)
     out.success(&format!("Set {var}={value}"));
+    out.field("var", var);
+    out.field("value", session::redact(var, value));
+    out.field("tracked", false);
 
     // 03-R6, 03-R7, 03-R8: track if session exists
-    if let Some(mut sess) = Session::load()? {
+    if let Some(mut sess) = crate::commands::session::load_for_tracking(out) {
         // 03-R14: skip tracking if value is identical to what's already tracked
-        if let Some(TrackedChange::Set { value: tracked_val, .. }) = sess.tracked.get(var) {
-            if tracked_val == value {
-                ex.save_session(&sess)?;
-                return Ok(0);
-            }
+        out.field("tracked", true);
+        if let Some(TrackedChange::Set { value: tracked_val, .. }) = sess.tracked.get(var)
+            && tracked_val == value
+        {
+            ex.save_session(&sess)?;
+            return Ok(0);
+        }
+
+        // 03-R9, 03-R14: snapshot first, unless the value is already in place
+        let current = std::env::var(var).ok();
+        if current.as_deref() != Some(value) {
+            snapshot::auto(&mut sess, &format!("before set {var}"));
         }
 
-        let result = sess.track_set(var, value);
+        sess.record("set", None, [(var.to_string(), current.clone(), Some(value.to_string()))]);
+        let result = sess.track_set(var, value, current.as_deref(), SourceKind::Set);
         ex.save_session(&sess)?;
 
         // 03-R11, 03-R12: display previous value and overwrite info
+        let kind = result.overwrite_kind.as_ref().map(|kind| match kind {
+            OverwriteKind::Tracked => "tracked",
+            OverwriteKind::Untracked => "untracked",
+        });
+        out.field("previous", result.previous.as_deref().map(|p| session::redact(var, p)));
+        out.field("overwrote", kind);
         if let Some(prev) = &result.previous {
-            let kind = match &result.overwrite_kind {
-                Some(OverwriteKind::Tracked) => " (was tracked)",
-                Some(OverwriteKind::Untracked) => " (was untracked)",
-                None => "",
-            };
-            out.key_value("Previous", &format!("{prev}{kind}"));
+            match kind {
+                Some(kind) => out.key_value("Previous", &format!("{prev} (was {kind})")),
+                None => out.key_value("Previous", prev),
+            }
         }
     }
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_redacts_sensitive_values() {
+        let mut out = Output::new();
+        out.start_json("set", None);
+        run(&out, &mut Exports::new(), "ENVISION_TEST_SET_TOKEN", "s3cret").unwrap();
+        assert_eq!(out.document()["value"], session::REDACTED);
+    }
+}
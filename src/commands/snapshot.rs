@@ -0,0 +1,163 @@
+use crate::config::Config;
+use crate::export::Exports;
+use crate::output::{self, Output};
+use crate::session::{self, Session};
+use crate::snapshot::{self, Snapshot};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+
+/// 00-R3: save a snapshot named by sequence number and optional label.
+pub fn save(out: &Output, ex: &mut Exports, name: Option<&str>) -> Result<u8, String> {
+    let mut session = require_session()?;
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+
+    let snap = session.save_snapshot(name, &env)?;
+    let (label, count) = (snap.label(), snap.env.len());
+    ex.save_session(&session)?;
+
+    out.success(&format!("Snapshot {label} saved"));
+    out.key_value("Variables", &count.to_string());
+    Ok(0)
+}
+
+pub fn list(out: &Output) -> Result<u8, String> {
+    let session = require_session()?;
+
+    if session.snapshots.is_empty() {
+        out.info("No snapshots");
+        return Ok(0);
+    }
+
+    for snap in &session.snapshots {
+        out.info(&format!(
+            "  {}  {}  {}",
+            out.bold(&snap.label()),
+            output::format_timestamp(snap.created_at),
+            out.dim(&format!("{} vars, {} tracked", snap.env.len(), snap.tracked.len())),
+        ));
+    }
+    Ok(0)
+}
+
+pub fn show(out: &Output, key: &str) -> Result<u8, String> {
+    let session = require_session()?;
+    let snap = find(&session, key)?;
+
+    out.key_value("Snapshot", &snap.label());
+    out.key_value("Created", &output::format_timestamp(snap.created_at));
+    out.key_value("Variables", &snap.env.len().to_string());
+    out.key_value("Tracked", &snap.tracked.len().to_string());
+
+    let current: BTreeMap<String, String> = std::env::vars().collect();
+    let plan = snapshot::restore_plan(&current, snap);
+    if plan.is_empty() {
+        out.info("Matches the current environment");
+    } else {
+        out.info(&format!("Restoring would change {} variable(s):", plan.len()));
+        print_plan(out, &plan);
+    }
+    Ok(0)
+}
+
+/// Bring the shell back to exactly the snapshot's state, including the
+/// tracked changes recorded at the time it was taken.
+pub fn restore(out: &Output, ex: &mut Exports, key: &str, force: bool) -> Result<u8, String> {
+    let mut session = require_session()?;
+    let snap = find(&session, key)?.clone();
+
+    let current: BTreeMap<String, String> = std::env::vars().collect();
+    let plan = snapshot::restore_plan(&current, &snap);
+
+    out.info(&format!("Restore snapshot {} ({} change(s)):", snap.label(), plan.len()));
+    print_plan(out, &plan);
+
+    if !force {
+        prompt_confirmation(&snap)?;
+    }
+
+    for (var, value) in &plan {
+        match value {
+            Some(value) => ex.set_var(var, value),
+            None => ex.unset_var(var),
+        }
+    }
+
+    // Sensitive variables were not captured and keep their current tracking
+    let mut tracked = snap.tracked.clone();
+    tracked.extend(session.tracked.iter()
+        .filter(|(var, _)| session::is_sensitive_var(var))
+        .map(|(var, change)| (var.clone(), change.clone())));
+
+    // Undo puts back the tracking of every variable the restore touches
+    let retracked: Vec<String> = session.tracked.keys().chain(tracked.keys()).cloned().collect();
+    session.record_retracking("snapshot restore", None, plan.into_iter()
+        .map(|(var, value)| {
+            let old = current.get(&var).cloned();
+            (var, old, value)
+        }), retracked.iter());
+    session.tracked = tracked;
+    ex.save_session(&session)?;
+
+    out.success(&format!("Restored snapshot {}", snap.label()));
+    Ok(0)
+}
+
+pub fn delete(out: &Output, ex: &mut Exports, key: &str) -> Result<u8, String> {
+    let mut session = require_session()?;
+    let snap = session.remove_snapshot(key)
+        .ok_or_else(|| format!("No snapshot '{key}'. Run 'envision snapshot list' to see snapshots."))?;
+    ex.save_session(&session)?;
+
+    out.success(&format!("Deleted snapshot {}", snap.label()));
+    Ok(0)
+}
+
+/// Take an automatic pre-change snapshot if enabled (`ENVISION_AUTO_SNAPSHOT=1`).
+/// 00-R4, 03-R9, 04-R7, 05-R4
+pub fn auto(session: &mut Session, note: &str) {
+    let config = Config::from_env();
+    if !config.auto_snapshot {
+        return;
+    }
+    let env: BTreeMap<String, String> = std::env::vars().collect();
+    session.auto_snapshot(note, &env, config.snapshot_limit);
+}
+
+/// 00-R1, 00-R2: snapshots require an active session.
+fn require_session() -> Result<Session, String> {
+    Session::load()?
+        .ok_or_else(|| "No active session. Run 'envision session init' first.".into())
+}
+
+fn find<'a>(session: &'a Session, key: &str) -> Result<&'a Snapshot, String> {
+    session.find_snapshot(key)
+        .ok_or_else(|| format!("No snapshot '{key}'. Run 'envision snapshot list' to see snapshots."))
+}
+
+fn print_plan(out: &Output, plan: &[(String, Option<String>)]) {
+    for (var, value) in plan {
+        match value {
+            Some(value) => out.info(&format!("  set {var}={value}")),
+            None => out.info(&format!("  unset {var}")),
+        }
+    }
+}
+
+fn prompt_confirmation(snap: &Snapshot) -> Result<(), String> {
+    if !io::stdin().is_terminal() {
+        return Err("Cannot prompt for confirmation: not a terminal. Use --force to skip.".into());
+    }
+
+    eprint!("Restore snapshot {}? [y/N] ", snap.label());
+    io::stderr().flush().ok();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)
+        .map_err(|e| format!("Failed to read input: {e}"))?;
+
+    if input.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err("Restore cancelled".into())
+    }
+}
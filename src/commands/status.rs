@@ -1,34 +1,97 @@
-use crate::output::Output;
-use crate::session::{self, Session};
+use crate::ignore::Ignore;
+use crate::output::{self, Output};
+use crate::session::{self, Damage, Session, TrackedChange, UntrackedChange};
+use serde_json::{Value, json};
 use std::collections::BTreeMap;
 
 /// 02-R1 through 02-R11
-pub fn run(out: &Output) -> Result<u8, String> {
+pub fn run(out: &Output, verbose: bool) -> Result<u8, String> {
     // 02-R1: error if no session
-    // 02-R9: flag if baseline missing/corrupted (handled by Session::load error path)
-    let session = Session::load()?
-        .ok_or("No active session. Run 'envision session init' first.")?;
+    // 02-R9: flag if baseline missing/corrupted
+    let session = match Session::read() {
+        Ok(Some(session)) => session,
+        Ok(None) => return Err("No active session. Run 'envision session init' first.".into()),
+        Err(damage) => return Ok(report_damage(out, &damage)),
+    };
 
+    out.field("session", json!({
+        "id": session.id,
+        "name": session.name,
+        "owner": session.owner,
+        "parent": session.parent.as_ref().map(|l| &l.parent_id),
+        "baseline_at": session.created_at,
+    }));
     out.key_value("Session", &session.id);
+    if let Some(name) = &session.name {
+        out.key_value("Name", name);
+    }
+    if let Some(owner) = &session.owner {
+        out.key_value("Shell", &owner.describe());
+    }
+    if let Some(lineage) = &session.parent {
+        out.key_value("Parent", &lineage.parent_id);
+    }
 
     // Show profile name if one is loaded
-    if let Ok(profile) = std::env::var("ENVISION_PROFILE") {
-        if !profile.is_empty() {
-            out.key_value("Profile", &profile);
-        }
+    let profile = std::env::var("ENVISION_PROFILE").ok().filter(|p| !p.is_empty());
+    out.field("profile", &profile);
+    if let Some(profile) = &profile {
+        out.key_value("Profile", profile);
     }
 
-    // 02-R4: count tracked variables
-    out.key_value("Tracked", &session.tracked.len().to_string());
+    // 02-R4: count tracked variables (removals included)
+    let removals = session.tracked.values()
+        .filter(|c| matches!(c, TrackedChange::Unset { .. }))
+        .count();
+    let tracked = if removals > 0 {
+        format!("{} ({removals} unset)", session.tracked.len())
+    } else {
+        session.tracked.len().to_string()
+    };
+    out.key_value("Tracked", &tracked);
+    out.field("tracked", session.tracked.iter()
+        .map(|(var, change)| tracked_json(&session, var, change))
+        .collect::<Vec<_>>());
+    if verbose {
+        for (var, change) in &session.tracked {
+            out.info(&format!("    {}", describe_tracked(out, &session, var, change)));
+        }
+    }
 
     // 02-R3: display baseline timestamp
-    out.key_value("Baseline", &format_timestamp(session.created_at));
+    out.key_value("Baseline", &output::format_timestamp(session.created_at));
+    out.field("baseline_comparable", session.baseline_problem.is_none());
 
-    // 02-R5, 02-R7, 02-R8: dirty/clean state (based on untracked changes)
+    // Child sessions: this shell's layer against the whole chain
     let current_env: BTreeMap<String, String> = std::env::vars().collect();
-    let dirty = session::count_untracked(&session, &current_env) > 0;
+    let ignore = Ignore::load();
+    if let Some(lineage) = session.parent.as_ref().filter(|_| session.baseline_problem.is_none()) {
+        let since_parent = session::count_changed(&session.baseline, &session.hasher, &current_env, &ignore);
+        let since_root = session::count_changed(&session.root_baseline(), &session.hasher, &current_env, &ignore);
+        out.field("since_parent", since_parent);
+        out.field("since_top_level", since_root);
+        out.key_value("Since parent", &format!("{since_parent} change(s)"));
+        out.key_value("Since top level", &format!("{since_root} change(s) (session {})", lineage.root_id));
+    }
+
+    // 02-R5, 02-R7, 02-R8: dirty/clean state (based on untracked changes)
+    let untracked = session::list_untracked(&session, &current_env, &ignore);
+    let dirty = !untracked.is_empty();
+    out.key_value("Untracked", &untracked.len().to_string());
+    out.field("untracked", untracked.iter()
+        .map(|change| untracked_json(&session, change, &current_env))
+        .collect::<Vec<_>>());
+    out.field("state", if dirty { "dirty" } else { "clean" });
+    if verbose {
+        for change in &untracked {
+            out.info(&format!("    {}", describe_untracked(&session, change, &current_env)));
+        }
+    }
     if dirty {
         out.warn("State: dirty");
+        if !verbose {
+            out.info("Run 'envision status -v' to list untracked changes.");
+        }
     } else {
         out.success("State: clean");
     }
@@ -37,40 +100,98 @@ pub fn run(out: &Output) -> Result<u8, String> {
     if dirty { Ok(1) } else { Ok(0) }
 }
 
-fn format_timestamp(epoch_secs: u64) -> String {
-    let secs = epoch_secs;
-    let days = secs / 86400;
-    let time_secs = secs % 86400;
-    let hours = time_secs / 3600;
-    let minutes = (time_secs % 3600) / 60;
-    let seconds = time_secs % 60;
+/// `FOO=bar  (profile dev (/home/me/dev.profile.sh), 2024-02-05 14:25:02 UTC)`
+/// List variables such as PATH show the entries that changed instead.
+pub fn describe_tracked(out: &Output, session: &Session, var: &str, change: &TrackedChange) -> String {
+    let what = match change {
+        TrackedChange::Set { value, .. } => match session.list_change(var, value) {
+            Some(entries) => format!("{var}: {entries}"),
+            None => format!("{var}={value}"),
+        },
+        TrackedChange::Unset { .. } => format!("unset {var}"),
+    };
+    let source = match change.source() {
+        Some(source) => format!("{}, {}", source.describe(), output::format_timestamp(source.at)),
+        None => "source not recorded".into(),
+    };
+    format!("{what}  {}", out.dim(&format!("({source})")))
+}
 
-    // Civil date from day count (algorithm from Howard Hinnant)
-    let z = days as i64 + 719468;
-    let era = if z >= 0 { z } else { z - 146096 } / 146097;
-    let doe = (z - era * 146097) as u64;
-    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
-    let y = yoe as i64 + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 };
-    let y = if m <= 2 { y + 1 } else { y };
+/// A tracked change in JSON documents.
+pub fn tracked_json(session: &Session, var: &str, change: &TrackedChange) -> Value {
+    let sensitive = session::is_sensitive_var(var);
+    match change {
+        TrackedChange::Set { value, previous, source } => json!({
+            "var": var,
+            "action": "set",
+            "value": session::redact(var, value),
+            "previous": previous.as_deref().map(|p| session::redact(var, p)),
+            "entries": if sensitive { None } else { session.list_change(var, value) },
+            "source": source,
+        }),
+        TrackedChange::Unset { previous, source } => json!({
+            "var": var,
+            "action": "unset",
+            "previous": session::redact(var, previous),
+            "source": source,
+        }),
+    }
+}
 
-    format!("{y:04}-{m:02}-{d:02} {hours:02}:{minutes:02}:{seconds:02} UTC")
+fn untracked_json(session: &Session, change: &UntrackedChange, env: &BTreeMap<String, String>) -> Value {
+    let (var, kind) = match change {
+        UntrackedChange::Added(var) => (var, "added"),
+        UntrackedChange::Removed(var) => (var, "removed"),
+        UntrackedChange::Modified(var) => (var, "modified"),
+    };
+    let entries = env.get(var).and_then(|value| session.list_change(var, value));
+    json!({ "var": var, "change": kind, "entries": entries })
+}
+
+/// `EDITOR (modified)`, or the changed entries for list variables.
+fn describe_untracked(session: &Session, change: &UntrackedChange, env: &BTreeMap<String, String>) -> String {
+    let var = change.var();
+    match change {
+        UntrackedChange::Added(_) => format!("{var} (added)"),
+        UntrackedChange::Removed(_) => format!("{var} (removed)"),
+        UntrackedChange::Modified(_) => match env.get(var).and_then(|value| session.list_change(var, value)) {
+            Some(entries) => format!("{var} (modified: {entries})"),
+            None => format!("{var} (modified)"),
+        },
+    }
+}
+
+/// 02-R9, 00-R9: flag damaged session data, say what is still readable,
+/// and exit non-zero.
+fn report_damage(out: &Output, damage: &Damage) -> u8 {
+    out.field("state", "degraded");
+    out.field("problem", &damage.problem);
+    out.field("salvaged_tracked", damage.salvaged.tracked.as_ref().map(BTreeMap::len));
+    out.field("lost", damage.salvaged.lost());
+    out.error(&damage.problem);
+    if let Some(tracked) = &damage.salvaged.tracked {
+        out.key_value("Tracked", &format!("{} (salvaged, read-only)", tracked.len()));
+    }
+    let lost = damage.salvaged.lost();
+    if !lost.is_empty() {
+        out.key_value("Lost", &lost.join(", "));
+    }
+    out.warn("State: degraded (changes are not being tracked)");
+    out.info("Run 'envision session repair' to start a fresh session from what can be salvaged.");
+    1
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session::SourceKind;
 
     #[test]
-    fn format_timestamp_known_date() {
-        assert_eq!(format_timestamp(1704067200), "2024-01-01 00:00:00 UTC");
-    }
-
-    #[test]
-    fn format_timestamp_with_time() {
-        assert_eq!(format_timestamp(1707142995), "2024-02-05 14:23:15 UTC");
+    fn tracked_json_redacts_sensitive_values() {
+        let mut session = Session::new(&BTreeMap::new()).unwrap();
+        session.track_set("API_TOKEN", "s3cret", None, SourceKind::Set);
+        let json = tracked_json(&session, "API_TOKEN", &session.tracked["API_TOKEN"]);
+        assert_eq!(json["value"], session::REDACTED);
+        assert!(!json.to_string().contains("s3cret"));
     }
 }
@@ -0,0 +1,138 @@
+use crate::commands::profile::{CHECKSUM_VAR, PROFILE_VAR};
+use crate::commands::snapshot;
+use crate::export::Exports;
+use crate::output::Output;
+use crate::session::{self, Session, SourceKind, TrackedChange};
+use crate::session_file::{ProfileInfo, SessionFile};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+
+/// 00-F01: save the session and the loaded profile's metadata to a file.
+pub fn export(out: &Output, path: &str, force: bool) -> Result<u8, String> {
+    let session = Session::load()?
+        .ok_or("No active session. Run 'envision session init' first.")?;
+
+    let profile = std::env::var(PROFILE_VAR)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .map(|name| ProfileInfo { name, checksum: std::env::var(CHECKSUM_VAR).ok() });
+
+    let path = Path::new(path);
+    SessionFile::new(&session, profile)?.write(path, force)?;
+
+    out.success(&format!("Session exported to {}", path.display()));
+    out.key_value("Session", &session.id);
+    out.key_value("Tracked", &session.tracked.len().to_string());
+
+    let sensitive: Vec<&str> = session.tracked.keys()
+        .map(String::as_str)
+        .filter(|var| session::is_sensitive_var(var))
+        .collect();
+    if !sensitive.is_empty() {
+        out.warn(&format!("The file contains values of sensitive variables: {}", sensitive.join(", ")));
+    }
+    Ok(0)
+}
+
+/// Apply the tracked changes of an exported session to this shell, tracking
+/// them in the current session like any other change.
+pub fn import(out: &Output, ex: &mut Exports, path: &str, yes: bool) -> Result<u8, String> {
+    let file = SessionFile::read(Path::new(path))?;
+    let imported = file.session()?;
+
+    // Names end up in shell statements; never trust them from a file
+    for var in imported.tracked.keys() {
+        session::validate_var_name(var)
+            .map_err(|e| format!("{path}: {e}"))?;
+    }
+
+    let current: BTreeMap<String, String> = std::env::vars().collect();
+    let plan: Vec<(String, Option<String>)> = imported.tracked.iter()
+        .map(|(var, change)| match change {
+            TrackedChange::Set { value, .. } => (var.clone(), Some(value.clone())),
+            TrackedChange::Unset { .. } => (var.clone(), None),
+        })
+        .filter(|(var, target)| current.get(var) != target.as_ref())
+        .collect();
+
+    if plan.is_empty() {
+        out.success(&format!("Nothing to import: this shell already matches session {}", imported.id));
+        return Ok(0);
+    }
+
+    out.info(&format!("Import {} change(s) from session {}:", plan.len(), imported.id));
+    for (var, target) in &plan {
+        match target {
+            Some(value) => out.info(&format!("  set {var}={value}")),
+            None => out.info(&format!("  unset {var}")),
+        }
+    }
+    if !yes {
+        prompt_confirmation()?;
+    }
+
+    let mut sess = crate::commands::session::ensure_session(out, ex)?;
+    if let Some(sess) = sess.as_mut() {
+        snapshot::auto(sess, &format!("before import {path}"));
+    }
+
+    for (var, target) in &plan {
+        match target {
+            Some(value) => ex.set_var(var, value),
+            None => ex.unset_var(var),
+        }
+    }
+
+    // Carry the exporting shell's profile markers along with its changes
+    let markers: Vec<(&str, String)> = file.profile.iter()
+        .flat_map(|p| [(PROFILE_VAR, Some(p.name.clone())), (CHECKSUM_VAR, p.checksum.clone())])
+        .filter_map(|(var, value)| Some((var, value?)))
+        .filter(|(var, value)| current.get(*var) != Some(value))
+        .collect();
+    for (var, value) in &markers {
+        ex.set_var(var, value);
+    }
+
+    if let Some(mut sess) = sess {
+        let journaled = plan.iter()
+            .map(|(var, target)| (var.clone(), current.get(var).cloned(), target.clone()))
+            .chain(markers.iter()
+                .map(|(var, value)| (var.to_string(), current.get(*var).cloned(), Some(value.clone()))));
+        sess.record("import", None, journaled);
+
+        let source = SourceKind::Import { session: imported.id.clone() };
+        for (var, target) in &plan {
+            match target {
+                Some(value) => { sess.track_set(var, value, current.get(var).map(String::as_str), source.clone()); }
+                None => { sess.track_unset(var, current.get(var).map(String::as_str), source.clone()); }
+            }
+        }
+        ex.save_session(&sess)?;
+    }
+
+    out.success(&format!("Imported {} change(s) from session {}", plan.len(), imported.id));
+    if let Some(profile) = &file.profile {
+        out.key_value("Profile", &profile.name);
+    }
+    Ok(0)
+}
+
+fn prompt_confirmation() -> Result<(), String> {
+    if !io::stdin().is_terminal() {
+        return Err("Cannot prompt for confirmation: not a terminal. Use --yes to skip.".into());
+    }
+
+    eprint!("Apply these changes? [y/N] ");
+    io::stderr().flush().ok();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)
+        .map_err(|e| format!("Failed to read input: {e}"))?;
+
+    if input.trim().eq_ignore_ascii_case("y") {
+        Ok(())
+    } else {
+        Err("Import cancelled".into())
+    }
+}
@@ -1,10 +1,14 @@
+use crate::commands::snapshot;
 use crate::export::Exports;
 use crate::output::Output;
-use crate::session::{self, PreviousKind, Session};
+use crate::session::{self, PreviousKind, SourceKind};
 
 pub fn run(out: &Output, ex: &mut Exports, var: &str) -> Result<u8, String> {
     // 04-R2: validate variable name exists in environment
     let current_value = std::env::var(var).ok();
+    out.field("var", var);
+    out.field("previous", current_value.as_deref().map(|p| session::redact(var, p)));
+    out.field("tracked", false);
 
     // 04-R12: warn but succeed if variable doesn't exist
     if current_value.is_none() {
@@ -25,20 +29,39 @@ pub fn run(out: &Output, ex: &mut Exports, var: &str) -> Result<u8, String> {
     out.success(&format!("Unset {var} (was: {prev})"));
 
     // 04-R4, 04-R5, 04-R6: track if session exists
-    if let Some(mut sess) = Session::load()? {
-        let result = sess.track_unset(var);
+    if let Some(mut sess) = crate::commands::session::load_for_tracking(out) {
+        // 04-R7: snapshot before removing
+        snapshot::auto(&mut sess, &format!("before unset {var}"));
+
+        sess.record("unset", None, [(var.to_string(), Some(prev.clone()), None)]);
+        let result = sess.track_unset(var, Some(&prev), SourceKind::Unset);
         ex.save_session(&sess)?;
 
         // 04-R10: indicate whether it was tracked, untracked, or original
-        if result.previous.is_some() {
-            let kind = match result.previous_kind {
-                PreviousKind::Tracked => "tracked",
-                PreviousKind::Original => "original",
-                PreviousKind::Untracked => "untracked",
-            };
-            out.key_value("Was", kind);
-        }
+        let kind = match result.previous_kind {
+            PreviousKind::Tracked => "tracked",
+            PreviousKind::Original => "original",
+            PreviousKind::Untracked => "untracked",
+        };
+        out.field("tracked", true);
+        out.field("was", kind);
+        out.key_value("Was", kind);
     }
 
     Ok(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_redacts_sensitive_values() {
+        // SAFETY: test-only, no other test touches this variable
+        unsafe { std::env::set_var("ENVISION_TEST_UNSET_TOKEN", "s3cret"); }
+        let mut out = Output::new();
+        out.start_json("unset", None);
+        run(&out, &mut Exports::new(), "ENVISION_TEST_UNSET_TOKEN").unwrap();
+        assert_eq!(out.document()["previous"], session::REDACTED);
+    }
+}
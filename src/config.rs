@@ -0,0 +1,78 @@
+use crate::output::Format;
+use crate::store::Storage;
+use clap::ValueEnum;
+
+/// Runtime settings, read from `ENVISION_*` environment variables.
+pub struct Config {
+    /// Take a snapshot before every mutating command (00-R4, 03-R9). Off
+    /// unless asked for: each one is a copy of the environment kept in the
+    /// session.
+    pub auto_snapshot: bool,
+    /// Maximum number of auto-snapshots kept; older ones are pruned.
+    pub snapshot_limit: usize,
+    /// Keep full original values in new sessions' baselines.
+    pub full_baseline: bool,
+    /// Storage backend for new sessions (00-R5).
+    pub storage: Storage,
+    /// Output format when `--format` is not given.
+    pub format: Format,
+    /// File descriptor for JSON output when `--json-fd` is not given.
+    pub json_fd: Option<u32>,
+}
+
+const AUTO_SNAPSHOT_VAR: &str = "ENVISION_AUTO_SNAPSHOT";
+const SNAPSHOT_LIMIT_VAR: &str = "ENVISION_SNAPSHOT_LIMIT";
+const DEFAULT_SNAPSHOT_LIMIT: usize = 5;
+const STORE_VAR: &str = "ENVISION_STORE";
+const FULL_BASELINE_VAR: &str = "ENVISION_FULL_BASELINE";
+const FORMAT_VAR: &str = "ENVISION_FORMAT";
+const JSON_FD_VAR: &str = "ENVISION_JSON_FD";
+
+impl Config {
+    pub fn from_env() -> Self {
+        let auto_snapshot = flag(AUTO_SNAPSHOT_VAR);
+        let snapshot_limit = std::env::var(SNAPSHOT_LIMIT_VAR)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SNAPSHOT_LIMIT);
+
+        let full_baseline = flag(FULL_BASELINE_VAR);
+        let storage = std::env::var(STORE_VAR)
+            .ok()
+            .and_then(|v| Storage::from_str(&v, true).ok())
+            .unwrap_or_default();
+
+        let format = std::env::var(FORMAT_VAR)
+            .ok()
+            .and_then(|v| Format::from_str(&v, true).ok())
+            .unwrap_or_default();
+        let json_fd = std::env::var(JSON_FD_VAR).ok().and_then(|v| v.parse().ok());
+
+        Self { auto_snapshot, snapshot_limit, full_baseline, storage, format, json_fd }
+    }
+}
+
+/// Whether the `var` switch is on. Unset or any other value means off.
+fn flag(var: &str) -> bool {
+    std::env::var(var).is_ok_and(|value| is_on(&value))
+}
+
+/// `1`, `on`, `true` and `yes`, in any case.
+fn is_on(value: &str) -> bool {
+    ["1", "on", "true", "yes"].iter().any(|on| value.eq_ignore_ascii_case(on))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_accept_the_usual_spellings() {
+        for value in ["1", "on", "true", "TRUE", "yes"] {
+            assert!(is_on(value), "{value}");
+        }
+        for value in ["", "0", "off", "false", "no", "2"] {
+            assert!(!is_on(value), "{value}");
+        }
+    }
+}
@@ -1,7 +1,10 @@
+use crate::ignore::Ignore;
 use crate::session::{self, Session};
+use crate::store::{self, Storage};
 use std::collections::BTreeMap;
 
 pub const SESSION_ID_VAR: &str = "ENVISION_SESSION_ID";
+pub const SESSION_NAME_VAR: &str = "ENVISION_SESSION_NAME";
 pub const TRACKED_COUNT_VAR: &str = "ENVISION_TRACKED";
 pub const DIRTY_VAR: &str = "ENVISION_DIRTY";
 
@@ -12,11 +15,26 @@ pub struct Exports {
     /// Most recently saved session, used by update_banner_vars() to avoid
     /// reading the stale ENVISION_SESSION env var from the parent shell.
     last_session: Option<Session>,
+    /// Set when the last saved session payload is close to exec limits.
+    size_warning: Option<String>,
+    /// Session files to write once the command has succeeded, by session ID.
+    pending_files: BTreeMap<String, String>,
+    /// Session files to delete once the queued ones have been written.
+    pending_removals: Vec<String>,
+    /// Set once the session has been removed from the shell.
+    ended: bool,
 }
 
 impl Exports {
     pub fn new() -> Self {
-        Self { statements: Vec::new(), last_session: None }
+        Self {
+            statements: Vec::new(),
+            last_session: None,
+            size_warning: None,
+            pending_files: BTreeMap::new(),
+            pending_removals: Vec::new(),
+            ended: false,
+        }
     }
 
     /// Queue `export VAR='value'`, escaping single quotes in the value.
@@ -30,38 +48,109 @@ impl Exports {
         self.statements.push(format!("unset {var}"));
     }
 
-    /// Queue the session env var export.
+    /// Queue the session env var export. A file-backed session's file is
+    /// only written by write_files(), after everything else has succeeded.
+    /// A session too large for the environment moves to file storage.
     pub fn save_session(&mut self, session: &Session) -> Result<(), String> {
-        self.statements.push(session.export_statement()?);
-        self.last_session = Some(session.clone());
+        let mut session = session.clone();
+        let (mut statement, mut file) = session.export_statement()?;
+        self.size_warning = None;
+        if session.storage.is_env() {
+            if session::fits_env(statement.len()) {
+                self.size_warning = session::env_size_warning(statement.len());
+            } else {
+                let len = statement.len();
+                session.storage = Storage::File;
+                (statement, file) = session.export_statement()?;
+                self.size_warning = Some(session::moved_to_file_warning(len, &session.location()));
+            }
+        }
+        if let Some(data) = file {
+            self.pending_files.insert(session.id.clone(), data);
+        }
+        self.statements.push(statement);
+        self.last_session = Some(session);
         Ok(())
     }
 
-    /// Compute and queue banner state env vars (session ID, tracked count, dirty flag).
+    /// Queue removal of the session env var; the banner vars follow in
+    /// update_banner_vars().
+    pub fn end_session(&mut self) {
+        self.unset_var(session::SESSION_VAR);
+        self.last_session = None;
+        self.size_warning = None;
+        self.pending_files.clear();
+        self.ended = true;
+    }
+
+    /// Queue deletion of a session file, done by write_files().
+    pub fn remove_file(&mut self, id: &str) {
+        self.pending_removals.push(id.to_string());
+    }
+
+    /// Write the session files queued by save_session(), then delete the
+    /// ones queued by remove_file(). Called once the command has succeeded,
+    /// so a failed command leaves them untouched.
+    pub fn write_files(&mut self) -> Result<(), String> {
+        for (id, data) in std::mem::take(&mut self.pending_files) {
+            store::write(&id, &data)?;
+        }
+        for id in std::mem::take(&mut self.pending_removals) {
+            store::remove(&id)?;
+        }
+        Ok(())
+    }
+
+    /// Warning about the size of the last saved session, if any.
+    pub fn size_warning(&self) -> Option<&str> {
+        self.size_warning.as_deref()
+    }
+
+    /// Compute and queue banner state env vars (session ID and name, tracked
+    /// count, dirty flag).
     /// Uses the last saved session if available, otherwise loads from env.
-    pub fn update_banner_vars(&mut self) -> Result<(), String> {
+    pub fn update_banner_vars(&mut self) {
         let session = match self.last_session.take() {
             Some(s) => Some(s),
-            None => Session::load()?,
+            None if self.ended => None,
+            None => match Session::read() {
+                Ok(session) => session,
+                // 00-R10: damaged session data shows as dirty; the id and
+                // tracked count keep their last known values
+                Err(_) => {
+                    self.set_var(DIRTY_VAR, "1");
+                    return;
+                }
+            },
         };
 
         match session {
             Some(session) => {
                 self.set_var(SESSION_ID_VAR, &session.id);
+                match &session.name {
+                    Some(name) => self.set_var(SESSION_NAME_VAR, name),
+                    None => self.unset_var(SESSION_NAME_VAR),
+                }
                 self.set_var(TRACKED_COUNT_VAR, &session.tracked.len().to_string());
 
                 let current_env: BTreeMap<String, String> = std::env::vars().collect();
-                let untracked = session::count_untracked(&session, &current_env);
+                let untracked = session::list_untracked(&session, &current_env, &Ignore::load()).len();
                 let dirty = if untracked > 0 { "1" } else { "0" };
                 self.set_var(DIRTY_VAR, dirty);
             }
             None => {
                 self.unset_var(SESSION_ID_VAR);
+                self.unset_var(SESSION_NAME_VAR);
                 self.unset_var(TRACKED_COUNT_VAR);
                 self.unset_var(DIRTY_VAR);
             }
         }
-        Ok(())
+    }
+
+    /// Statements queued so far.
+    #[cfg(test)]
+    pub fn statements(&self) -> &[String] {
+        &self.statements
     }
 
     /// Write all queued statements to stdout.
@@ -71,3 +160,26 @@ impl Exports {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::hash_value;
+
+    #[test]
+    fn oversized_session_moves_to_a_file() {
+        // Hashes barely compress, unlike repeated text
+        let env: BTreeMap<String, String> = (0..3000)
+            .map(|i| (format!("V{i}"), (0..8).map(|j| format!("{:016x}", hash_value(&format!("{i}.{j}")))).collect()))
+            .collect();
+        let mut session = Session::new(&BTreeMap::new()).unwrap();
+        session.save_snapshot(Some("big"), &env).unwrap();
+
+        let mut ex = Exports::new();
+        ex.save_session(&session).unwrap();
+        assert!(ex.statements[0].contains(store::FILE_PREFIX));
+        assert!(ex.pending_files.contains_key(&session.id));
+        assert!(ex.size_warning().unwrap().contains("now stored in"));
+        assert_eq!(ex.last_session.as_ref().unwrap().storage, Storage::File);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::session::hash_value;
+use crate::store;
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher13;
+use siphasher::sip128::SipHasher13 as SipHasher128;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Per-user secret that session keys are derived from, kept in the state
+/// directory. Without it the baseline hashes exported into the environment
+/// can't be checked against guessed values.
+const SECRET_FILE: &str = "fingerprint.key";
+
+/// Hashed with the session key and stored next to the salt, so a secret
+/// that was deleted or replaced is detected instead of making every baseline
+/// variable look changed.
+const CHECK_INPUT: &str = "envision fingerprint check";
+
+/// How a session's baseline values are hashed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    /// Unkeyed FNV-1a. Sessions written before v5 use it, as do sessions
+    /// started where no secret could be created.
+    #[default]
+    Fnv1a,
+    /// SipHash-1-3 keyed from the per-user secret and a random salt. Child
+    /// sessions and forks share the salt of the session they came from.
+    Keyed { salt: u64, check: u64 },
+}
+
+/// Computes baseline fingerprints for one session.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Hasher {
+    #[default]
+    Fnv1a,
+    Keyed(SipHasher13),
+}
+
+impl Hasher {
+    pub fn hash(&self, value: &str) -> u64 {
+        match self {
+            Hasher::Fnv1a => hash_value(value),
+            Hasher::Keyed(sip) => sip.hash(value.as_bytes()),
+        }
+    }
+}
+
+impl Scheme {
+    /// Keyed scheme with a fresh salt for a new session.
+    pub fn generate() -> Result<(Self, Hasher), String> {
+        let secret = secret()?;
+        let salt = u64::from_le_bytes(random_bytes()?);
+        let hasher = derive(&secret, salt);
+        Ok((Scheme::Keyed { salt, check: hasher.hash(CHECK_INPUT) }, hasher))
+    }
+
+    /// Hasher for a stored session. Fails when the secret the session was
+    /// keyed with is gone: its baseline can no longer be compared.
+    pub fn hasher(&self) -> Result<Hasher, String> {
+        match *self {
+            Scheme::Fnv1a => Ok(Hasher::Fnv1a),
+            Scheme::Keyed { salt, check } => {
+                let hasher = derive(&secret()?, salt);
+                if hasher.hash(CHECK_INPUT) != check {
+                    return Err(format!(
+                        "Baseline cannot be compared: the fingerprint key in {} is missing or was replaced",
+                        store::state_dir()?.join(SECRET_FILE).display()
+                    ));
+                }
+                Ok(hasher)
+            }
+        }
+    }
+}
+
+fn derive(secret: &[u8; 16], salt: u64) -> Hasher {
+    let key = SipHasher128::new_with_key(secret).hash(&salt.to_le_bytes());
+    Hasher::Keyed(SipHasher13::new_with_keys(key.h1, key.h2))
+}
+
+/// The per-user secret, created on first use. Read once per process.
+#[cfg(not(test))]
+pub fn secret() -> Result<[u8; 16], String> {
+    static SECRET: OnceLock<Result<[u8; 16], String>> = OnceLock::new();
+    SECRET.get_or_init(|| load_or_create(&store::state_dir()?)).clone()
+}
+
+/// Tests never touch the real state directory.
+#[cfg(test)]
+pub fn secret() -> Result<[u8; 16], String> {
+    static SECRET: OnceLock<[u8; 16]> = OnceLock::new();
+    Ok(*SECRET.get_or_init(|| *b"envision-testkey"))
+}
+
+fn load_or_create(dir: &Path) -> Result<[u8; 16], String> {
+    let path = dir.join(SECRET_FILE);
+    let damaged = |e: String| format!("Cannot read fingerprint key {}: {e}", path.display());
+
+    match File::open(&path) {
+        Ok(mut file) => {
+            let mut secret = [0u8; 16];
+            let mut extra = [0u8; 1];
+            file.read_exact(&mut secret).map_err(|e| damaged(e.to_string()))?;
+            if file.read(&mut extra).map_err(|e| damaged(e.to_string()))? != 0 {
+                return Err(damaged("unexpected length".into()));
+            }
+            return Ok(secret);
+        }
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(damaged(e.to_string())),
+        Err(_) => {}
+    }
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(|e| format!("Cannot create session storage at {}: {e}", dir.display()))?;
+
+    // Write the key in full under a private name, then link it into place:
+    // a shell starting at the same time never sees a partial key
+    let secret = random_bytes()?;
+    let tmp = dir.join(format!("{SECRET_FILE}.{}.tmp", std::process::id()));
+    let linked = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(&secret))
+        .and_then(|()| fs::hard_link(&tmp, &path));
+    let _ = fs::remove_file(&tmp);
+    match linked {
+        Ok(()) => Ok(secret),
+        // Another shell created it first
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => load_or_create(dir),
+        Err(e) => Err(format!("Cannot write fingerprint key {}: {e}", path.display())),
+    }
+}
+
+/// Bytes from the kernel's random source.
+pub fn random_bytes<const N: usize>() -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .map_err(|e| format!("Cannot read /dev/urandom: {e}"))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_hashes_depend_on_salt() {
+        let secret = secret().unwrap();
+        let a = derive(&secret, 1);
+        let b = derive(&secret, 2);
+        assert_ne!(a.hash("value"), b.hash("value"));
+        assert_eq!(a.hash("value"), derive(&secret, 1).hash("value"));
+        assert_ne!(a.hash("value"), Hasher::Fnv1a.hash("value"));
+    }
+
+    #[test]
+    fn replaced_secret_is_detected() {
+        let (scheme, hasher) = Scheme::generate().unwrap();
+        assert_eq!(scheme.hasher().unwrap().hash("x"), hasher.hash("x"));
+
+        let Scheme::Keyed { salt, check } = scheme else { panic!("expected a keyed scheme") };
+        let tampered = Scheme::Keyed { salt, check: check ^ 1 };
+        assert!(tampered.hasher().unwrap_err().contains("missing or was replaced"));
+    }
+
+    #[test]
+    fn legacy_sessions_use_fnv() {
+        let scheme: Scheme = serde_json::from_str("\"fnv1a\"").unwrap();
+        assert_eq!(scheme.hasher().unwrap().hash("bar"), hash_value("bar"));
+    }
+
+    #[test]
+    fn secret_file_is_private_and_stable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("envision-key-{}", std::process::id()));
+        let first = load_or_create(&dir).unwrap();
+        assert_eq!(load_or_create(&dir).unwrap(), first);
+        let mode = fs::metadata(dir.join(SECRET_FILE)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1, "temporary key file left behind");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
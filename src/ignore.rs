@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+/// Variables that change on their own: the shell updates them between
+/// commands, and prompt tools keep per-prompt state in the environment.
+/// They are never reported as untracked changes.
+const DEFAULT_PATTERNS: &[&str] = &[
+    "PWD", "OLDPWD", "_", "SHLVL", "BASH_EXECUTION_STRING", "COLUMNS", "LINES",
+    "STARSHIP_*", "POSH_*", "P9K_*", "_P9K_*", "DIRENV_*", "__vsc_*",
+];
+
+/// Extra patterns, comma-separated: `ENVISION_IGNORE='TMUX*,!PWD'`.
+pub const IGNORE_VAR: &str = "ENVISION_IGNORE";
+
+/// One pattern per line in `$XDG_CONFIG_HOME/envision/ignore`.
+const IGNORE_FILE: &str = "ignore";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    /// `!PATTERN` takes a variable back out of an earlier rule.
+    include: bool,
+}
+
+/// Glob rules for variables left out of change detection. Rules are checked
+/// in order (defaults, config file, `ENVISION_IGNORE`) and the last one that
+/// matches decides, so `!PWD` in the config file undoes the default.
+#[derive(Debug, Clone)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// Built-in defaults only.
+    pub fn defaults() -> Self {
+        let mut ignore = Self { rules: Vec::new() };
+        ignore.extend(DEFAULT_PATTERNS.iter().copied());
+        ignore
+    }
+
+    /// Defaults plus the user's config file and `ENVISION_IGNORE`. A missing
+    /// or unreadable config file adds nothing.
+    pub fn load() -> Self {
+        let mut ignore = Self::defaults();
+        if let Some(text) = config_file().and_then(|path| std::fs::read_to_string(path).ok()) {
+            ignore.extend(text.lines().filter(|line| !line.trim_start().starts_with('#')));
+        }
+        if let Ok(value) = std::env::var(IGNORE_VAR) {
+            ignore.extend(value.split(','));
+        }
+        ignore
+    }
+
+    fn extend<'a>(&mut self, patterns: impl IntoIterator<Item = &'a str>) {
+        for pattern in patterns.into_iter().map(str::trim).filter(|p| !p.is_empty()) {
+            let rule = match pattern.strip_prefix('!') {
+                Some(pattern) => Rule { pattern: pattern.to_string(), include: true },
+                None => Rule { pattern: pattern.to_string(), include: false },
+            };
+            self.rules.push(rule);
+        }
+    }
+
+    pub fn is_ignored(&self, var: &str) -> bool {
+        self.rules.iter()
+            .rev()
+            .find(|rule| glob_match(&rule.pattern, var))
+            .is_some_and(|rule| !rule.include)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/envision/ignore`, falling back to `~/.config`.
+fn config_file() -> Option<PathBuf> {
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("envision").join(IGNORE_FILE))
+}
+
+/// Shell-style match: `*` is any run of characters, `?` any single one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character
+                Some((after, tried)) => {
+                    p = after;
+                    t = tried + 1;
+                    star = Some((after, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("PWD", "PWD"));
+        assert!(!glob_match("PWD", "OLDPWD"));
+        assert!(glob_match("STARSHIP_*", "STARSHIP_SESSION_KEY"));
+        assert!(glob_match("*_HISTORY", "PSQL_HISTORY"));
+        assert!(glob_match("T?UX*", "TMUX_PANE"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("A*B", "AXXC"));
+        assert!(glob_match("A*B*C", "AxxBxxBxC"));
+    }
+
+    #[test]
+    fn defaults_cover_shell_noise() {
+        let ignore = Ignore::defaults();
+        for var in ["PWD", "OLDPWD", "_", "SHLVL", "STARSHIP_CMD_STATUS"] {
+            assert!(ignore.is_ignored(var), "{var}");
+        }
+        assert!(!ignore.is_ignored("PATH"));
+    }
+
+    #[test]
+    fn later_rules_win() {
+        let mut ignore = Ignore::defaults();
+        ignore.extend("TMUX*, !PWD".split(','));
+        assert!(ignore.is_ignored("TMUX_PANE"));
+        assert!(!ignore.is_ignored("PWD"));
+        assert!(ignore.is_ignored("OLDPWD"));
+    }
+}
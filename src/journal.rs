@@ -0,0 +1,82 @@
+use crate::session::TrackedChange;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One variable change in the session's journal. Entries written by the same
+/// command share an operation number. Values of sensitive variables are
+/// stored as `REDACTED`, and only the newest operations are kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub op: u32,
+    pub at: u64,
+    /// Command that made the change: `set`, `unset`, `profile`, `clear`, ...
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// File the profile was loaded from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_path: Option<String>,
+    pub var: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+/// An operation on the undo or redo stack: its journal operation number, its
+/// variable changes as applied, and the tracked state of each touched
+/// variable to reinstate when it is undone or redone (`None` = not tracked).
+/// The changes are kept here in full because the journal is capped and
+/// redacted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoEntry {
+    pub op: u32,
+    pub changes: Vec<Change>,
+    pub tracked: BTreeMap<String, Option<TrackedChange>>,
+}
+
+/// A single variable change to record: name, old value, new value
+/// (`None` = not set).
+pub type Change = (String, Option<String>, Option<String>);
+
+impl JournalEntry {
+    /// Check an entry against optional variable and profile filters.
+    pub fn matches(&self, var: Option<&str>, profile: Option<&str>) -> bool {
+        var.is_none_or(|v| self.var == v)
+            && profile.is_none_or(|p| self.profile.as_deref() == Some(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(var: &str, profile: Option<&str>) -> JournalEntry {
+        JournalEntry {
+            op: 1,
+            at: 0,
+            command: "profile".into(),
+            profile: profile.map(str::to_string),
+            profile_path: None,
+            var: var.into(),
+            old: None,
+            new: Some("x".into()),
+        }
+    }
+
+    #[test]
+    fn matches_without_filters() {
+        assert!(entry("FOO", None).matches(None, None));
+    }
+
+    #[test]
+    fn matches_by_var_and_profile() {
+        let e = entry("FOO", Some("dev"));
+        assert!(e.matches(Some("FOO"), None));
+        assert!(e.matches(None, Some("dev")));
+        assert!(e.matches(Some("FOO"), Some("dev")));
+        assert!(!e.matches(Some("BAR"), None));
+        assert!(!e.matches(None, Some("prod")));
+        assert!(!entry("FOO", None).matches(None, Some("dev")));
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Link from a child session to the session of the shell it was started
+/// from. Only the child's own changes are tracked; the parent's stay part of
+/// its baseline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lineage {
+    pub parent_id: String,
+    /// Top-level session of the chain; equal to `parent_id` one level down.
+    pub root_id: String,
+    /// Where the top-level baseline differs from this session's baseline:
+    /// the top-level hash, or `None` if the variable did not exist there.
+    /// Nested shells share most of their environment, so this stays small.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub root_delta: BTreeMap<String, Option<u64>>,
+}
+
+impl Lineage {
+    /// Link a new session with `baseline` to its parent. `root_baseline` is
+    /// the parent's view of the top-level baseline.
+    pub fn new(
+        parent_id: &str,
+        root_id: &str,
+        root_baseline: &BTreeMap<String, u64>,
+        baseline: &BTreeMap<String, u64>,
+    ) -> Self {
+        let mut root_delta = BTreeMap::new();
+        for (var, hash) in root_baseline {
+            if baseline.get(var) != Some(hash) {
+                root_delta.insert(var.clone(), Some(*hash));
+            }
+        }
+        for var in baseline.keys() {
+            if !root_baseline.contains_key(var) {
+                root_delta.insert(var.clone(), None);
+            }
+        }
+
+        Self {
+            parent_id: parent_id.to_string(),
+            root_id: root_id.to_string(),
+            root_delta,
+        }
+    }
+
+    /// Rebuild the top-level baseline from this session's baseline.
+    pub fn root_baseline(&self, baseline: &BTreeMap<String, u64>) -> BTreeMap<String, u64> {
+        let mut root = baseline.clone();
+        for (var, hash) in &self.root_delta {
+            match hash {
+                Some(hash) => { root.insert(var.clone(), *hash); }
+                None => { root.remove(var); }
+            }
+        }
+        root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        pairs.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn delta_reconstructs_root_baseline() {
+        let root = map(&[("HOME", 1), ("PATH", 2), ("GONE", 3)]);
+        let child = map(&[("HOME", 1), ("PATH", 20), ("ADDED", 4)]);
+        let lineage = Lineage::new("parent", "root", &root, &child);
+
+        assert_eq!(lineage.root_delta.len(), 3);
+        assert_eq!(lineage.root_delta.get("ADDED"), Some(&None));
+        assert_eq!(lineage.root_baseline(&child), root);
+    }
+
+    #[test]
+    fn identical_baselines_need_no_delta() {
+        let base = map(&[("HOME", 1)]);
+        let lineage = Lineage::new("parent", "parent", &base, &base);
+        assert!(lineage.root_delta.is_empty());
+    }
+}
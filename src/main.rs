@@ -1,49 +1,139 @@
 mod cli;
+mod codec;
 mod commands;
+mod config;
 mod export;
+mod fingerprint;
+mod ignore;
+mod journal;
+mod lineage;
 mod output;
+mod owner;
+mod pathlist;
+mod schema;
 mod session;
+mod session_file;
+mod snapshot;
+mod store;
+#[cfg(test)]
+mod testutil;
 
-use cli::{Cli, Command, SessionAction};
+use cli::{Cli, Command, SessionAction, SnapshotAction};
+use config::Config;
 use export::Exports;
-use output::Output;
+use output::{Format, Output};
 use std::process;
 
 fn main() {
-    let out = Output::new();
+    let mut out = Output::new();
     let mut ex = Exports::new();
     let args = Cli::parse_filtered();
 
+    // Other commands keep reporting as text
+    let config = Config::from_env();
+    if args.format.unwrap_or(config.format) == Format::Json
+        && let Some(name) = json_command(&args.command)
+    {
+        out.start_json(name, args.json_fd.or(config.json_fd));
+    }
+
     // Track whether this command mutates env (needs banner var update)
     let mut mutating = true;
 
+    // 01-R12: session commands resolve ownership themselves
+    if !matches!(args.command, Command::Session { .. } | Command::Hook { .. } | Command::Banner) {
+        commands::session::warn_session_problems(&out);
+    }
+
     let result: Result<u8, String> = match args.command {
         Command::Session { action } => match action {
-            SessionAction::Init { force, resume } => commands::session::init(&out, &mut ex, force, resume),
+            SessionAction::Init { force, resume, full_baseline, store, child, name } => {
+                let opts = commands::session::InitOptions {
+                    force, resume, full_baseline, storage: store, child, name,
+                };
+                commands::session::init(&out, &mut ex, opts)
+            }
+            SessionAction::Repair => commands::session::repair(&out, &mut ex),
+            SessionAction::End { revert, keep } => commands::session::end(&out, &mut ex, revert, keep),
+            SessionAction::Export { file, force } => {
+                mutating = false;
+                commands::transfer::export(&out, &file, force)
+            }
+            SessionAction::Import { file, yes } => commands::transfer::import(&out, &mut ex, &file, yes),
+            SessionAction::Rebase => commands::session::rebase(&out, &mut ex),
+            SessionAction::Fork => commands::session::fork(&out, &mut ex),
+            SessionAction::Claim => commands::session::claim(&out, &mut ex),
+            SessionAction::Discard => commands::session::discard(&out, &mut ex),
+            SessionAction::Prune => { mutating = false; commands::session::prune(&out) },
         },
         Command::Profile { path, yes, dry_run } => commands::profile::run(&out, &mut ex, &path, yes, dry_run),
         Command::Set { var, value } => commands::set::run(&out, &mut ex, &var, &value),
         Command::Unset { var } => commands::unset::run(&out, &mut ex, &var),
+        Command::Adopt { vars, all } => commands::adopt::run(&out, &mut ex, &vars, all),
         Command::Clear { force } => commands::clear::run(&out, &mut ex, force),
+        Command::Undo { count } => commands::history::undo(&out, &mut ex, count),
+        Command::Redo { count } => commands::history::redo(&out, &mut ex, count),
+        Command::Snapshot { action } => match action {
+            SnapshotAction::Save { name } => commands::snapshot::save(&out, &mut ex, name.as_deref()),
+            SnapshotAction::Restore { snapshot, force } => {
+                commands::snapshot::restore(&out, &mut ex, &snapshot, force)
+            }
+            SnapshotAction::Delete { snapshot } => commands::snapshot::delete(&out, &mut ex, &snapshot),
+            SnapshotAction::List => { mutating = false; commands::snapshot::list(&out) },
+            SnapshotAction::Show { snapshot } => { mutating = false; commands::snapshot::show(&out, &snapshot) },
+        },
         // Non-mutating commands
         Command::Hook { shell } => { mutating = false; commands::hook::run(&shell) },
-        Command::Status => { mutating = false; commands::status::run(&out) },
-        Command::Banner => { mutating = false; commands::banner::run() },
+        Command::Status { verbose } => { mutating = false; commands::status::run(&out, verbose) },
+        Command::Diff { sides, filter } => {
+            mutating = false;
+            commands::diff::run(&out, &sides, filter.as_deref())
+        }
+        Command::Banner => { mutating = false; commands::banner::run(&out) },
+        Command::Get { var } => { mutating = false; commands::get::run(&out, &var) },
+        Command::Log { var, profile, limit } => {
+            mutating = false;
+            commands::log::run(&out, var.as_deref(), profile.as_deref(), limit)
+        }
     };
 
+    // Session files are written only once the command itself has succeeded
+    let result = result.and_then(|code| ex.write_files().map(|()| code));
     match result {
         Ok(code) => {
             if mutating {
-                if let Err(e) = ex.update_banner_vars() {
-                    out.warn(&format!("Could not update banner state: {e}"));
-                }
+                ex.update_banner_vars();
             }
+            if let Some(warning) = ex.size_warning() {
+                out.warn(warning);
+            }
+            out.finish(Some(code));
             ex.flush();
             process::exit(code as i32);
         }
         Err(msg) => {
-            out.error(&format!("Error: {msg}"));
+            if out.is_json() {
+                out.error(&msg);
+            } else {
+                out.error(&format!("Error: {msg}"));
+            }
+            out.finish(None);
             process::exit(1);
         }
     }
 }
+
+/// Name reported in the JSON document of commands that support
+/// `--format json`.
+fn json_command(command: &Command) -> Option<&'static str> {
+    match command {
+        Command::Status { .. } => Some("status"),
+        Command::Set { .. } => Some("set"),
+        Command::Unset { .. } => Some("unset"),
+        Command::Clear { .. } => Some("clear"),
+        Command::Profile { .. } => Some("profile"),
+        Command::Banner => Some("banner"),
+        Command::Session { action: SessionAction::Init { .. } } => Some("session init"),
+        _ => None,
+    }
+}
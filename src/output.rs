@@ -1,7 +1,35 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::cell::RefCell;
+use std::fs::OpenOptions;
 use std::io::{self, Write};
 
+/// Version of the JSON documents written with `--format json`. Bump it when
+/// a field is renamed, removed or changes meaning; adding fields is fine.
+pub const JSON_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Human,
+    Json,
+}
+
 pub struct Output {
     color: bool,
+    /// Set when results are reported as one JSON document instead of text.
+    json: Option<RefCell<Document>>,
+}
+
+/// JSON report being built up by a command.
+struct Document {
+    command: String,
+    /// File descriptor to write to; stderr when unset.
+    fd: Option<u32>,
+    fields: Map<String, Value>,
+    warnings: Vec<String>,
+    error: Option<String>,
 }
 
 const RESET: &str = "\x1b[0m";
@@ -15,7 +43,73 @@ impl Output {
     pub fn new() -> Self {
         let no_color_flag = std::env::args().any(|a| a == "--no-color");
         let color = !no_color_flag && std::env::var("NO_COLOR").is_err();
-        Self { color }
+        Self { color, json: None }
+    }
+
+    /// Report `command`'s results as JSON. Text from `success`, `info` and
+    /// `key_value` is dropped, warnings and errors go into the document.
+    pub fn start_json(&mut self, command: &str, fd: Option<u32>) {
+        self.color = false;
+        self.json = Some(RefCell::new(Document {
+            command: command.to_string(),
+            fd,
+            fields: Map::new(),
+            warnings: Vec::new(),
+            error: None,
+        }));
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json.is_some()
+    }
+
+    /// Add a field to the JSON document. Does nothing for text output.
+    pub fn field(&self, key: &str, value: impl Serialize) {
+        if let Some(doc) = &self.json {
+            let value = serde_json::to_value(value).unwrap_or(Value::Null);
+            doc.borrow_mut().fields.insert(key.to_string(), value);
+        }
+    }
+
+    /// Write the JSON document, if any, as a single line. `code` is the exit
+    /// code, `None` when the command failed.
+    pub fn finish(&self, code: Option<u8>) {
+        let (Some(doc), Some(report)) = (&self.json, self.report(code)) else { return };
+        let fd = doc.borrow().fd;
+        let line = format!("{report}\n");
+
+        let written = match fd {
+            Some(fd) => OpenOptions::new()
+                .append(true)
+                .open(format!("/dev/fd/{fd}"))
+                .and_then(|mut f| f.write_all(line.as_bytes())),
+            None => io::stderr().write_all(line.as_bytes()),
+        };
+        if let Err(e) = written {
+            let _ = writeln!(io::stderr(), "Cannot write JSON output to file descriptor {}: {e}", fd.unwrap_or(2));
+        }
+    }
+
+    /// The JSON document: fixed fields first, then the command's own.
+    fn report(&self, code: Option<u8>) -> Option<Value> {
+        let doc = self.json.as_ref()?.borrow();
+        let mut report = Map::new();
+        report.insert("version".into(), json!(JSON_VERSION));
+        report.insert("command".into(), json!(doc.command));
+        report.insert("ok".into(), json!(doc.error.is_none()));
+        report.insert("exit_code".into(), json!(code.unwrap_or(1)));
+        report.extend(doc.fields.clone());
+        report.insert("warnings".into(), json!(doc.warnings));
+        if let Some(error) = &doc.error {
+            report.insert("error".into(), json!(error));
+        }
+        Some(Value::Object(report))
+    }
+
+    /// The JSON document as a successful command would report it.
+    #[cfg(test)]
+    pub fn document(&self) -> Value {
+        self.report(Some(0)).expect("JSON output was started")
     }
 
     fn styled(&self, code: &str, text: &str) -> String {
@@ -27,18 +121,32 @@ impl Output {
     }
 
     pub fn success(&self, msg: &str) {
+        if self.is_json() {
+            return;
+        }
         let _ = writeln!(io::stderr(), "{}", self.styled(GREEN, msg));
     }
 
     pub fn info(&self, msg: &str) {
+        if self.is_json() {
+            return;
+        }
         let _ = writeln!(io::stderr(), "{}", msg);
     }
 
     pub fn warn(&self, msg: &str) {
+        if let Some(doc) = &self.json {
+            doc.borrow_mut().warnings.push(msg.to_string());
+            return;
+        }
         let _ = writeln!(io::stderr(), "{}", self.styled(YELLOW, msg));
     }
 
     pub fn error(&self, msg: &str) {
+        if let Some(doc) = &self.json {
+            doc.borrow_mut().error = Some(msg.to_string());
+            return;
+        }
         let _ = writeln!(io::stderr(), "{}", self.styled(RED, msg));
     }
 
@@ -50,7 +158,109 @@ impl Output {
         self.styled(DIM, msg)
     }
 
+    pub fn green(&self, msg: &str) -> String {
+        self.styled(GREEN, msg)
+    }
+
+    pub fn yellow(&self, msg: &str) -> String {
+        self.styled(YELLOW, msg)
+    }
+
+    pub fn red(&self, msg: &str) -> String {
+        self.styled(RED, msg)
+    }
+
     pub fn key_value(&self, key: &str, value: &str) {
+        if self.is_json() {
+            return;
+        }
         let _ = writeln!(io::stderr(), "  {}: {}", self.bold(key), value);
     }
 }
+
+/// Format epoch seconds as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_timestamp(epoch_secs: u64) -> String {
+    let secs = epoch_secs;
+    let days = secs / 86400;
+    let time_secs = secs % 86400;
+    let hours = time_secs / 3600;
+    let minutes = (time_secs % 3600) / 60;
+    let seconds = time_secs % 60;
+
+    // Civil date from day count (algorithm from Howard Hinnant)
+    let z = days as i64 + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = (z - era * 146097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = if m <= 2 { y + 1 } else { y };
+
+    format!("{y:04}-{m:02}-{d:02} {hours:02}:{minutes:02}:{seconds:02} UTC")
+}
+
+/// Format a duration in seconds as its two largest units: `45s`, `12m 5s`,
+/// `2h 14m`, `3d 4h`.
+pub fn format_duration(secs: u64) -> String {
+    let units = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let Some(first) = units.iter().position(|&(size, _)| secs >= size) else {
+        return "0s".into();
+    };
+    let (size, unit) = units[first];
+    let mut text = format!("{}{unit}", secs / size);
+    if let Some(&(next, next_unit)) = units.get(first + 1)
+        && secs % size >= next
+    {
+        text.push_str(&format!(" {}{next_unit}", secs % size / next));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_document_collects_fields_and_warnings() {
+        let mut out = Output::new();
+        out.field("ignored", 1);
+        assert!(out.report(Some(0)).is_none());
+
+        out.start_json("set", None);
+        out.field("var", "FOO");
+        out.warn("careful");
+        out.info("not in the document");
+        let report = out.report(Some(0)).unwrap();
+        assert_eq!(report, json!({
+            "version": JSON_VERSION, "command": "set", "ok": true, "exit_code": 0,
+            "var": "FOO", "warnings": ["careful"],
+        }));
+
+        out.error("boom");
+        let report = out.report(None).unwrap();
+        assert_eq!((report["ok"].clone(), report["error"].clone()), (json!(false), json!("boom")));
+    }
+
+    #[test]
+    fn format_timestamp_known_date() {
+        assert_eq!(format_timestamp(1704067200), "2024-01-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn format_timestamp_with_time() {
+        assert_eq!(format_timestamp(1707142995), "2024-02-05 14:23:15 UTC");
+    }
+
+    #[test]
+    fn format_duration_two_largest_units() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(725), "12m 5s");
+        assert_eq!(format_duration(8040), "2h 14m");
+        assert_eq!(format_duration(7200), "2h");
+        assert_eq!(format_duration(273600), "3d 4h");
+    }
+}
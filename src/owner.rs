@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Set by the shell hook to the shell's own PID (`$$`, `$fish_pid`). The hook
+/// runs envision inside a command substitution, so the parent process is a
+/// subshell rather than the shell itself.
+pub const SHELL_PID_VAR: &str = "ENVISION_SHELL_PID";
+
+/// The shell a session belongs to. 01-R6
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owner {
+    /// `None` when envision ran outside the shell hook, which is the only
+    /// reliable source of the shell's PID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+/// How the shell running a command relates to a session's owner. 01-R12
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnerStatus {
+    /// The command runs in the shell that owns the session.
+    Current,
+    /// The session was inherited from a shell that is still running, such
+    /// as the parent of a subshell or the shell a tmux server started from.
+    Foreign,
+    /// The owning shell has exited; the session was left behind.
+    Dead,
+    /// The PID of the owner or of the current shell was not recorded, so
+    /// the two cannot be told apart.
+    Unknown,
+}
+
+impl Owner {
+    /// Describe the shell this command is running in. Without the hook's
+    /// PID the shell is unknown: the parent process may be a subshell.
+    pub fn current() -> Self {
+        let pid = std::env::var(SHELL_PID_VAR)
+            .ok()
+            .and_then(|v| v.parse().ok());
+        Self { pid, tty: current_tty(), host: hostname() }
+    }
+
+    /// Classify this owner as seen from the `current` shell.
+    pub fn status(&self, current: &Owner) -> OwnerStatus {
+        self.classify(current, self.pid.is_some_and(pid_alive))
+    }
+
+    fn classify(&self, current: &Owner, alive: bool) -> OwnerStatus {
+        // PIDs on another host say nothing about this one
+        if let (Some(ours), Some(theirs)) = (&current.host, &self.host)
+            && ours != theirs
+        {
+            return OwnerStatus::Foreign;
+        }
+        let (Some(pid), Some(current_pid)) = (self.pid, current.pid) else {
+            return OwnerStatus::Unknown;
+        };
+        if pid == current_pid {
+            OwnerStatus::Current
+        } else if alive {
+            OwnerStatus::Foreign
+        } else {
+            OwnerStatus::Dead
+        }
+    }
+
+    /// Short description: `PID 4242 on /dev/pts/3 at buildhost`.
+    pub fn describe(&self) -> String {
+        let mut text = match self.pid {
+            Some(pid) => format!("PID {pid}"),
+            None => "unknown PID".to_string(),
+        };
+        if let Some(tty) = &self.tty {
+            text.push_str(&format!(" on {tty}"));
+        }
+        if let Some(host) = &self.host {
+            text.push_str(&format!(" at {host}"));
+        }
+        text
+    }
+}
+
+/// Terminal attached to stdin or stderr, if any.
+fn current_tty() -> Option<String> {
+    ["/proc/self/fd/0", "/proc/self/fd/2"].iter()
+        .filter_map(|fd| std::fs::read_link(fd).ok())
+        .map(|path| path.display().to_string())
+        .find(|path| path.starts_with("/dev/pts/") || path.starts_with("/dev/tty"))
+}
+
+pub fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Whether a process is running. Without /proc every process is assumed
+/// alive, so sessions are never wrongly reported as abandoned.
+fn pid_alive(pid: u32) -> bool {
+    !Path::new("/proc/self").exists() || Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner(pid: u32, host: &str) -> Owner {
+        Owner { pid: Some(pid), tty: None, host: Some(host.into()) }
+    }
+
+    #[test]
+    fn same_shell_is_current() {
+        assert_eq!(owner(10, "a").classify(&owner(10, "a"), true), OwnerStatus::Current);
+    }
+
+    #[test]
+    fn other_live_shell_is_foreign() {
+        assert_eq!(owner(10, "a").classify(&owner(20, "a"), true), OwnerStatus::Foreign);
+    }
+
+    #[test]
+    fn exited_shell_is_dead() {
+        assert_eq!(owner(10, "a").classify(&owner(20, "a"), false), OwnerStatus::Dead);
+    }
+
+    #[test]
+    fn other_host_is_foreign_even_with_same_pid() {
+        assert_eq!(owner(10, "a").classify(&owner(10, "b"), false), OwnerStatus::Foreign);
+    }
+
+    #[test]
+    fn missing_pid_is_unknown() {
+        let unrecorded = Owner { pid: None, tty: None, host: Some("a".into()) };
+        assert_eq!(unrecorded.classify(&owner(10, "a"), false), OwnerStatus::Unknown);
+        assert_eq!(owner(10, "a").classify(&unrecorded, true), OwnerStatus::Unknown);
+        assert_eq!(unrecorded.classify(&owner(10, "b"), false), OwnerStatus::Foreign);
+    }
+
+    #[test]
+    fn describe_includes_known_parts() {
+        let owner = Owner { pid: Some(7), tty: Some("/dev/pts/3".into()), host: Some("box".into()) };
+        assert_eq!(owner.describe(), "PID 7 on /dev/pts/3 at box");
+        assert_eq!(Owner { pid: Some(7), tty: None, host: None }.describe(), "PID 7");
+        assert_eq!(Owner { pid: None, tty: None, host: None }.describe(), "unknown PID");
+    }
+}
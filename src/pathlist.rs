@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+
+/// Colon-separated search paths. Changes to these are shown entry by entry
+/// instead of as a whole new value.
+const LIST_VARS: &[&str] = &[
+    "PATH", "MANPATH", "INFOPATH", "CDPATH",
+    "LD_LIBRARY_PATH", "DYLD_LIBRARY_PATH", "DYLD_FALLBACK_LIBRARY_PATH",
+    "LIBRARY_PATH", "CPATH", "C_INCLUDE_PATH", "CPLUS_INCLUDE_PATH", "PKG_CONFIG_PATH",
+    "PYTHONPATH", "PERL5LIB", "RUBYLIB", "NODE_PATH", "GOPATH", "CLASSPATH",
+    "XDG_DATA_DIRS", "XDG_CONFIG_DIRS", "FPATH", "TERMINFO_DIRS",
+];
+
+pub fn is_list_var(var: &str) -> bool {
+    LIST_VARS.contains(&var)
+}
+
+/// How the entries of a list variable changed. Entries added before the
+/// first kept entry are prepended, after the last one appended, and in
+/// between inserted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ListDiff {
+    pub prepended: Vec<String>,
+    pub appended: Vec<String>,
+    pub inserted: Vec<String>,
+    pub removed: Vec<String>,
+    /// Kept entries are in a different order.
+    pub reordered: bool,
+}
+
+/// Compare two values of a list variable. `None` when they are equal or
+/// share no entries, where the whole value says more than a list of edits.
+pub fn diff(old: &str, new: &str) -> Option<ListDiff> {
+    if old == new {
+        return None;
+    }
+    let old: Vec<&str> = entries(old);
+    let new: Vec<&str> = entries(new);
+    let old_set: BTreeSet<&str> = old.iter().copied().collect();
+    let new_set: BTreeSet<&str> = new.iter().copied().collect();
+
+    let kept: Vec<usize> = (0..new.len()).filter(|&i| old_set.contains(new[i])).collect();
+    let (&first, &last) = (kept.first()?, kept.last()?);
+
+    let mut diff = ListDiff::default();
+    for (i, entry) in new.iter().enumerate().filter(|(_, e)| !old_set.contains(*e)) {
+        let group = if i < first {
+            &mut diff.prepended
+        } else if i > last {
+            &mut diff.appended
+        } else {
+            &mut diff.inserted
+        };
+        group.push(entry.to_string());
+    }
+    diff.removed = old.iter()
+        .filter(|e| !new_set.contains(*e))
+        .map(|e| e.to_string())
+        .collect();
+
+    let order = |list: &[&str], keep: &BTreeSet<&str>| -> Vec<String> {
+        let mut seen = BTreeSet::new();
+        list.iter()
+            .filter(|e| keep.contains(*e) && seen.insert(**e))
+            .map(|e| e.to_string())
+            .collect()
+    };
+    diff.reordered = order(&old, &new_set) != order(&new, &old_set);
+    Some(diff)
+}
+
+/// Empty entries are dropped: they only come from stray colons.
+fn entries(value: &str) -> Vec<&str> {
+    value.split(':').filter(|e| !e.is_empty()).collect()
+}
+
+impl ListDiff {
+    /// `prepended /opt/bin; removed /usr/games`
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        for (label, list) in [
+            ("prepended", &self.prepended),
+            ("appended", &self.appended),
+            ("inserted", &self.inserted),
+            ("removed", &self.removed),
+        ] {
+            if !list.is_empty() {
+                parts.push(format!("{label} {}", list.join(", ")));
+            }
+        }
+        if self.reordered {
+            parts.push("reordered".into());
+        }
+        if parts.is_empty() {
+            // Only empty entries or duplicates changed
+            parts.push("entries unchanged".into());
+        }
+        parts.join("; ")
+    }
+}
+
+/// Describe a change from `old` to `new` entry by entry when `var` is a list
+/// variable and the values can be compared that way.
+pub fn describe_change(var: &str, old: &str, new: &str) -> Option<String> {
+    if !is_list_var(var) {
+        return None;
+    }
+    diff(old, new).map(|d| d.describe())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_added_entries_by_position() {
+        let d = diff("/usr/bin:/bin", "/opt/a:/usr/bin:/x:/bin:/opt/b").unwrap();
+        assert_eq!(d.prepended, ["/opt/a"]);
+        assert_eq!(d.inserted, ["/x"]);
+        assert_eq!(d.appended, ["/opt/b"]);
+        assert!(d.removed.is_empty());
+        assert!(!d.reordered);
+        assert_eq!(d.describe(), "prepended /opt/a; appended /opt/b; inserted /x");
+    }
+
+    #[test]
+    fn detects_removal_and_reordering() {
+        let d = diff("/a:/b:/c", "/c:/a").unwrap();
+        assert_eq!(d.removed, ["/b"]);
+        assert!(d.reordered);
+        assert_eq!(d.describe(), "removed /b; reordered");
+    }
+
+    #[test]
+    fn unrelated_values_are_not_listed() {
+        assert!(diff("/a:/b", "/c").is_none());
+        assert!(diff("/a", "/a").is_none());
+        assert!(describe_change("EDITOR", "vi", "vi:nano").is_none());
+    }
+}
@@ -0,0 +1,58 @@
+use serde_json::{Value, json};
+
+/// Version of the session layout written by this build. Bump it whenever
+/// the serialized form of `Session` changes. Changes that only add fields
+/// with serde defaults need no migration; the rest get one in `migrate`.
+///
+/// v1 is the unversioned layout: id, created_at, baseline and tracked. v2
+/// adds the version field and fields that all have serde defaults.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Upgrade a decoded session document to the current layout.
+/// Sessions without a version field are v1 (written before versioning).
+pub fn migrate(mut doc: Value) -> Result<Value, String> {
+    let obj = doc.as_object_mut()
+        .ok_or("Session data corrupted (expected a JSON object)")?;
+
+    let version = match obj.get("version") {
+        None => 1,
+        Some(v) => v.as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|&v| v >= 1)
+            .ok_or("Session data corrupted (bad schema version)")?,
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "Session was written by a newer envision (schema v{version}, this build \
+             supports up to v{CURRENT_VERSION}); upgrade envision to keep using it"
+        ));
+    }
+
+    obj.insert("version".into(), json!(CURRENT_VERSION));
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+
+    #[test]
+    fn unversioned_session_migrates_to_current() {
+        let doc = json!({"id": "abc", "created_at": 0, "baseline": {}, "tracked": {}});
+        let migrated = migrate(doc).unwrap();
+        assert_eq!(migrated["version"], json!(CURRENT_VERSION));
+
+        let session: Session = serde_json::from_value(migrated).unwrap();
+        assert!(session.journal.is_empty() && session.undo_stack.is_empty());
+        assert_eq!(session.op_seq, 0);
+    }
+
+    #[test]
+    fn newer_session_is_rejected_clearly() {
+        let doc = json!({"version": CURRENT_VERSION + 1, "id": "abc"});
+        let err = migrate(doc).unwrap_err();
+        assert!(err.contains("newer envision"));
+    }
+}
@@ -1,18 +1,76 @@
-use base64::{Engine, engine::general_purpose::STANDARD};
+use crate::codec;
+use crate::fingerprint::{self, Hasher, Scheme};
+use crate::ignore::Ignore;
+use crate::schema;
+use crate::journal::{Change, JournalEntry, UndoEntry};
+use crate::lineage::Lineage;
+use crate::owner::{self, Owner};
+use crate::pathlist;
+use crate::snapshot::{self, Snapshot};
+use crate::store::{self, Storage};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub const SESSION_VAR: &str = "ENVISION_SESSION";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
+    /// Schema version of the serialized layout (see `schema`).
+    pub version: u32,
     pub id: String,
+    /// Label given with `session init --name`, shown instead of the id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub created_at: u64,
+    /// Shell the session was started in. 01-R6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    /// Set for a child session started in a nested shell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Lineage>,
+    /// Backend the session is persisted with between commands.
+    #[serde(default, skip_serializing_if = "Storage::is_env")]
+    pub storage: Storage,
+    /// How baseline values are hashed.
+    #[serde(default)]
+    pub fingerprint: Scheme,
+    /// Hasher for `fingerprint`, resolved when the session is created or
+    /// decoded.
+    #[serde(skip)]
+    pub hasher: Hasher,
+    /// Why the baseline cannot be compared, set on decode when the
+    /// fingerprint key is missing or was replaced. Until `rebase`, changed
+    /// values go unnoticed; tracked changes, undo and clear still work.
+    #[serde(skip)]
+    pub baseline_problem: Option<String>,
     /// Baseline: variable name -> hash of original value.
     pub baseline: BTreeMap<String, u64>,
+    /// Full original values, only captured when the session is created with
+    /// a full baseline. Sensitive variables are left out (hash only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub originals: Option<BTreeMap<String, String>>,
     /// Tracked changes with full values.
     pub tracked: BTreeMap<String, TrackedChange>,
+    /// Saved snapshots, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<Snapshot>,
+    /// Last snapshot sequence number handed out.
+    #[serde(default)]
+    pub snapshot_seq: u32,
+    /// Append-only log of every change made through envision, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub journal: Vec<JournalEntry>,
+    /// Last journal operation number handed out.
+    #[serde(default)]
+    pub op_seq: u32,
+    /// Operations that `undo` can revert, most recent last.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub undo_stack: Vec<UndoEntry>,
+    /// Undone operations that `redo` can reapply, most recent last.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redo_stack: Vec<UndoEntry>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,12 +78,79 @@ pub enum TrackedChange {
     Set {
         value: String,
         previous: Option<String>,
+        /// Missing for changes tracked before v8.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<Source>,
     },
     Unset {
         previous: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<Source>,
     },
 }
 
+impl TrackedChange {
+    pub fn source(&self) -> Option<&Source> {
+        match self {
+            TrackedChange::Set { source, .. } | TrackedChange::Unset { source, .. } => source.as_ref(),
+        }
+    }
+}
+
+/// What made a tracked change, and when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Source {
+    #[serde(flatten)]
+    pub kind: SourceKind,
+    pub at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "by", rename_all = "lowercase")]
+pub enum SourceKind {
+    Set,
+    Unset,
+    Profile { name: String, path: String, checksum: String },
+    /// Applied from a session file exported by another shell.
+    Import { session: String },
+    Adopt,
+}
+
+impl Source {
+    pub fn now(kind: SourceKind) -> Self {
+        Self { kind, at: now_secs() }
+    }
+
+    /// Short description such as `profile dev (/home/me/dev.profile.sh)`.
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            SourceKind::Set => "set".into(),
+            SourceKind::Unset => "unset".into(),
+            SourceKind::Profile { name, path, .. } => format!("profile {name} ({path})"),
+            SourceKind::Import { session } => format!("import from session {session}"),
+            SourceKind::Adopt => "adopt".into(),
+        }
+    }
+}
+
+/// Name fragments marking a variable as sensitive. Sensitive values are never
+/// stored in full, even with a full baseline.
+const SENSITIVE_MARKERS: &[&str] = &[
+    "SECRET", "TOKEN", "PASSWORD", "PASSWD", "CREDENTIAL", "PRIVATE", "API_KEY",
+    "ACCESS_KEY", "AUTH",
+];
+
+/// Maximum number of operations kept on the undo stack.
+const UNDO_LIMIT: usize = 50;
+
+/// Maximum number of operations kept in the journal. Undo and redo journal
+/// operations of their own, so this is larger than `UNDO_LIMIT`.
+const JOURNAL_LIMIT: usize = 100;
+
+/// Stands in for the value of a sensitive variable where the value is only
+/// kept or shown for the record.
+pub const REDACTED: &str = "<redacted>";
+
 /// System-critical variables that warrant a warning before modification.
 const CRITICAL_VARS: &[&str] = &[
     "PATH", "HOME", "USER", "SHELL", "TERM", "LANG", "PWD", "OLDPWD",
@@ -34,64 +159,211 @@ const CRITICAL_VARS: &[&str] = &[
 
 impl Session {
     /// Create a new session from the current environment.
-    /// Stores only hashes of baseline values.
-    pub fn new(env: &BTreeMap<String, String>) -> Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("system clock before epoch")
-            .as_secs();
+    /// Stores only keyed hashes of baseline values, falling back to
+    /// unkeyed ones if no fingerprint key is available.
+    pub fn new(env: &BTreeMap<String, String>) -> Result<Self, String> {
+        let (fingerprint, hasher) = Scheme::generate().unwrap_or_default();
+        Self::with_fingerprint(env, fingerprint, hasher)
+    }
 
-        let id = generate_session_id(std::process::id(), now);
+    fn with_fingerprint(
+        env: &BTreeMap<String, String>,
+        fingerprint: Scheme,
+        hasher: Hasher,
+    ) -> Result<Self, String> {
+        let baseline = capture_baseline(env, &hasher);
+
+        Ok(Self {
+            version: schema::CURRENT_VERSION,
+            id: generate_session_id()?,
+            name: None,
+            created_at: now_secs(),
+            owner: Some(Owner::current()),
+            parent: None,
+            storage: Storage::Env,
+            fingerprint,
+            hasher,
+            baseline_problem: None,
+            baseline,
+            originals: None,
+            tracked: BTreeMap::new(),
+            snapshots: Vec::new(),
+            snapshot_seq: 0,
+            journal: Vec::new(),
+            op_seq: 0,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+        })
+    }
 
-        let baseline = env
+    /// Keep full original values alongside the baseline hashes so that
+    /// overwritten or removed baseline variables can be restored.
+    pub fn capture_originals(&mut self, env: &BTreeMap<String, String>) {
+        let originals = env
             .iter()
-            .filter(|(k, _)| k.as_str() != SESSION_VAR)
-            .map(|(k, v)| (k.clone(), hash_value(v)))
+            .filter(|(k, _)| self.baseline.contains_key(k.as_str()) && !is_sensitive_var(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
             .collect();
+        self.originals = Some(originals);
+    }
 
-        Self {
-            id,
-            created_at: now,
-            baseline,
-            tracked: BTreeMap::new(),
+    /// Create a child session nested under `parent`, the session inherited
+    /// from the shell this one was started from. The child keeps full
+    /// original values so that clearing it restores the parent's state.
+    /// Its baseline is hashed like the parent's, so the two stay comparable.
+    pub fn new_child(parent: &Session, env: &BTreeMap<String, String>) -> Result<Self, String> {
+        let mut child = Self::with_fingerprint(env, parent.fingerprint, parent.hasher)?;
+        child.capture_originals(env);
+        child.storage = parent.storage;
+        child.parent = Some(Lineage::new(
+            &parent.id,
+            parent.root_id(),
+            &parent.root_baseline(),
+            &child.baseline,
+        ));
+        Ok(child)
+    }
+
+    /// Accept `env` as the new baseline while keeping tracked changes, so
+    /// changes made outside envision stop counting as untracked. Tracked
+    /// variables keep their old baseline entry and original value: clear
+    /// still has to know whether envision added them. A baseline that could
+    /// not be compared is hashed afresh under a new key.
+    pub fn rebase(&mut self, env: &BTreeMap<String, String>) {
+        if self.baseline_problem.take().is_some() {
+            (self.fingerprint, self.hasher) = Scheme::generate().unwrap_or_default();
+            for (var, hash) in &mut self.baseline {
+                if let Some(value) = value_before(&self.tracked, self.originals.as_ref(), var) {
+                    *hash = self.hasher.hash(value);
+                }
+            }
+            // The parent's hashes are keyed differently now
+            if let Some(lineage) = &self.parent {
+                self.parent = Some(Lineage::new(&lineage.parent_id, &lineage.root_id, &self.baseline, &self.baseline));
+            }
+        }
+        let root = self.root_baseline();
+
+        let mut baseline = capture_baseline(env, &self.hasher);
+        for var in self.tracked.keys() {
+            match self.baseline.get(var) {
+                Some(hash) => { baseline.insert(var.clone(), *hash); }
+                None => { baseline.remove(var); }
+            }
+        }
+
+        if let Some(old) = self.originals.take() {
+            let mut originals: BTreeMap<String, String> = env.iter()
+                .filter(|(k, _)| baseline.contains_key(k.as_str()))
+                .filter(|(k, _)| !is_sensitive_var(k) && !self.tracked.contains_key(k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            for var in self.tracked.keys() {
+                if let Some(value) = old.get(var) {
+                    originals.insert(var.clone(), value.clone());
+                }
+            }
+            self.originals = Some(originals);
+        }
+
+        self.baseline = baseline;
+        if let Some(lineage) = &self.parent {
+            self.parent = Some(Lineage::new(&lineage.parent_id, &lineage.root_id, &root, &self.baseline));
+        }
+    }
+
+    /// ID of the top-level session this one descends from (itself if none).
+    pub fn root_id(&self) -> &str {
+        self.parent.as_ref().map_or(&self.id, |p| &p.root_id)
+    }
+
+    /// Baseline of the top-level session this one descends from.
+    pub fn root_baseline(&self) -> BTreeMap<String, u64> {
+        match &self.parent {
+            Some(lineage) => lineage.root_baseline(&self.baseline),
+            None => self.baseline.clone(),
         }
     }
 
-    /// Encode session as base64 string for storing in an env var.
+    /// Encode session for storing in an env var or session file.
     pub fn encode(&self) -> Result<String, String> {
-        let json = serde_json::to_string(self)
-            .map_err(|e| format!("Failed to serialize session: {e}"))?;
-        Ok(STANDARD.encode(json.as_bytes()))
+        codec::encode(self)
     }
 
-    /// Decode session from a base64 env var value.
+    /// Decode session from an env var value or session file. A missing or
+    /// replaced fingerprint key only makes the baseline incomparable; the
+    /// rest of the session stays usable.
     pub fn decode(encoded: &str) -> Result<Self, String> {
-        let bytes = STANDARD
-            .decode(encoded)
-            .map_err(|e| format!("Session data corrupted (bad base64): {e}"))?;
-        let json = std::str::from_utf8(&bytes)
-            .map_err(|e| format!("Session data corrupted (bad utf8): {e}"))?;
-        serde_json::from_str(json)
-            .map_err(|e| format!("Session data corrupted (bad json): {e}"))
+        let mut session = codec::decode(encoded)?;
+        match session.fingerprint.hasher() {
+            Ok(hasher) => session.hasher = hasher,
+            Err(problem) => session.baseline_problem = Some(problem),
+        }
+        Ok(session)
     }
 
     /// Load session from the ENVISION_SESSION env var, if present.
+    /// Damaged session data is an error pointing at `session repair`.
     pub fn load() -> Result<Option<Self>, String> {
-        match std::env::var(SESSION_VAR) {
-            Ok(val) if !val.is_empty() => Ok(Some(Self::decode(&val)?)),
-            _ => Ok(None),
-        }
+        Self::read().map_err(|damage| damage.message())
+    }
+
+    /// Read the session from the ENVISION_SESSION env var, if present.
+    /// The variable holds either the encoded session or a `file:<id>`
+    /// pointer to an on-disk session. Damaged data is reported with
+    /// whatever could be salvaged so callers can degrade gracefully. 00-R9
+    pub fn read() -> Result<Option<Self>, Box<Damage>> {
+        let val = match std::env::var(SESSION_VAR) {
+            Ok(val) if !val.is_empty() => val,
+            _ => return Ok(None),
+        };
+
+        let file_id = val.strip_prefix(store::FILE_PREFIX).map(str::to_string);
+        let encoded = match &file_id {
+            Some(id) => store::read(id).map_err(|problem| Box::new(Damage {
+                problem,
+                file_id: file_id.clone(),
+                salvaged: Salvage::default(),
+            }))?,
+            None => val,
+        };
+
+        Self::decode(&encoded).map(Some).map_err(|problem| Box::new(Damage {
+            problem,
+            file_id,
+            salvaged: codec::salvage(&encoded),
+        }))
     }
 
-    /// Return the shell export statement to persist this session.
-    pub fn export_statement(&self) -> Result<String, String> {
+    /// Return the shell export statement to persist this session. With
+    /// file storage only a pointer is exported, and the data for the session
+    /// file is returned alongside for the caller to write.
+    pub fn export_statement(&self) -> Result<(String, Option<String>), String> {
         let encoded = self.encode()?;
-        Ok(format!("export {SESSION_VAR}='{encoded}'"))
+        match self.storage {
+            Storage::Env => Ok((format!("export {SESSION_VAR}='{encoded}'"), None)),
+            Storage::File => {
+                let pointer = format!("export {SESSION_VAR}='{}{}'", store::FILE_PREFIX, self.id);
+                Ok((pointer, Some(encoded)))
+            }
+        }
+    }
+
+    /// Human-readable storage location. 01-R10
+    pub fn location(&self) -> String {
+        match self.storage {
+            Storage::Env => SESSION_VAR.to_string(),
+            Storage::File => store::session_path(&self.id)
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|e| e),
+        }
     }
 
     /// Record a set operation. Returns info about what was overwritten.
+    /// `current` is the value the variable holds in the environment, kept as
+    /// the original when the baseline has no value for it.
     /// 03-R6, 03-R7, 03-R8
-    pub fn track_set(&mut self, var: &str, value: &str) -> SetResult {
+    pub fn track_set(&mut self, var: &str, value: &str, current: Option<&str>, source: SourceKind) -> SetResult {
         let previous = self.tracked_value(var);
 
         let overwrite_kind = if self.tracked.contains_key(var) {
@@ -102,44 +374,259 @@ impl Session {
             None
         };
 
+        // Keep the pre-session value so clear restores the original,
+        // not whatever an earlier tracked change left behind.
+        let original = match self.tracked.get(var) {
+            Some(TrackedChange::Set { previous, .. }) => previous.clone(),
+            Some(TrackedChange::Unset { previous, .. }) => Some(previous.clone()),
+            None => previous.clone().or_else(|| current.map(str::to_string)),
+        };
+
         self.tracked.insert(var.to_string(), TrackedChange::Set {
             value: value.to_string(),
-            previous: previous.clone(),
+            previous: original,
+            source: Some(Source::now(source)),
         });
 
         SetResult { previous, overwrite_kind }
     }
 
     /// Record an unset operation. Returns info about what was removed.
+    /// `current` is the value the variable holds in the environment, used
+    /// for original and untracked variables the session has no value for.
     /// 04-R4, 04-R5, 04-R6
-    pub fn track_unset(&mut self, var: &str) -> UnsetResult {
-        let previous = self.tracked_value(var);
+    pub fn track_unset(&mut self, var: &str, current: Option<&str>, source: SourceKind) -> UnsetResult {
+        let previous = current.map(str::to_string).or_else(|| self.tracked_value(var));
 
         let previous_kind = if self.tracked.contains_key(var) {
             PreviousKind::Tracked
-        } else if self.baseline.contains_key(var) {
+        } else if self.in_baseline(var) {
             PreviousKind::Original
         } else {
             PreviousKind::Untracked
         };
 
-        if let Some(prev) = &previous {
-            self.tracked.insert(var.to_string(), TrackedChange::Unset {
-                previous: prev.clone(),
-            });
+        let original = match self.tracked.get(var) {
+            Some(TrackedChange::Set { previous: Some(orig), .. }) => Some(orig.clone()),
+            // Added through the tool and now removed again: back to baseline.
+            // A baseline variable set over without a known original stays
+            // unknown; `current` is the tool's own value, not the original.
+            Some(TrackedChange::Set { previous: None, .. }) => None,
+            Some(TrackedChange::Unset { previous, .. }) => Some(previous.clone()),
+            None => previous,
+        };
+
+        match original {
+            Some(prev) => {
+                self.tracked.insert(var.to_string(), TrackedChange::Unset {
+                    previous: prev,
+                    source: Some(Source::now(source)),
+                });
+            }
+            None => {
+                self.tracked.remove(var);
+            }
         }
 
-        UnsetResult { previous, previous_kind }
+        UnsetResult { previous_kind }
     }
 
-    /// Get the last known value from tracked changes.
-    /// Since baseline only stores hashes, we can only return values
-    /// from tracked changes (which store full values).
+    /// Get the last known value from tracked changes, falling back to the
+    /// original value when the session keeps a full baseline.
     fn tracked_value(&self, var: &str) -> Option<String> {
         match self.tracked.get(var) {
             Some(TrackedChange::Set { value, .. }) => Some(value.clone()),
             Some(TrackedChange::Unset { .. }) => None,
-            None => None,
+            None => self.original_value(var).map(str::to_string),
+        }
+    }
+
+    /// Original value of a baseline variable, if the full baseline was kept.
+    pub fn original_value(&self, var: &str) -> Option<&str> {
+        self.originals.as_ref()?.get(var).map(String::as_str)
+    }
+
+    /// Value a variable had before envision changed it, when known.
+    pub fn value_before(&self, var: &str) -> Option<&str> {
+        value_before(&self.tracked, self.originals.as_ref(), var)
+    }
+
+    /// How a list variable such as PATH changed entry by entry, from its
+    /// value before the session to `value`. None when that value isn't
+    /// known, as with hash-only baselines; callers show the whole value.
+    pub fn list_change(&self, var: &str, value: &str) -> Option<String> {
+        pathlist::describe_change(var, self.value_before(var)?, value)
+    }
+
+    /// Save a snapshot of `env` and the current tracked changes.
+    /// 00-R3: every snapshot gets the next sequence number.
+    pub fn save_snapshot(
+        &mut self,
+        name: Option<&str>,
+        env: &BTreeMap<String, String>,
+    ) -> Result<&Snapshot, String> {
+        if let Some(name) = name {
+            snapshot::validate_name(name)?;
+            if self.find_snapshot(name).is_some() {
+                return Err(format!("Snapshot '{name}' already exists"));
+            }
+        }
+
+        self.snapshot_seq += 1;
+        let snap = Snapshot::capture(self.snapshot_seq, name, now_secs(), env, &self.tracked);
+        self.snapshots.push(snap);
+        Ok(self.snapshots.last().expect("snapshot just pushed"))
+    }
+
+    /// Take an automatic snapshot before a mutating command, then prune the
+    /// oldest auto-snapshots beyond `limit`. Named snapshots are never pruned.
+    /// 00-R4, 03-R9, 04-R7, 05-R4
+    pub fn auto_snapshot(&mut self, note: &str, env: &BTreeMap<String, String>, limit: usize) {
+        self.snapshot_seq += 1;
+        let mut snap = Snapshot::capture(self.snapshot_seq, None, now_secs(), env, &self.tracked);
+        snap.auto = true;
+        snap.note = Some(note.to_string());
+        self.snapshots.push(snap);
+
+        let autos = self.snapshots.iter().filter(|s| s.auto).count();
+        let mut excess = autos.saturating_sub(limit);
+        self.snapshots.retain(|s| {
+            if s.auto && excess > 0 {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Find a snapshot by name or sequence number.
+    pub fn find_snapshot(&self, key: &str) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|s| s.matches(key))
+    }
+
+    /// Remove a snapshot by name or sequence number.
+    pub fn remove_snapshot(&mut self, key: &str) -> Option<Snapshot> {
+        let idx = self.snapshots.iter().position(|s| s.matches(key))?;
+        Some(self.snapshots.remove(idx))
+    }
+
+    /// Append one command's variable changes to the journal under a new
+    /// operation number and make it undoable. Must be called before the
+    /// tracked state is updated. Commands that changed nothing leave no entry.
+    /// `profile` is the name and file of a loaded profile.
+    pub fn record(
+        &mut self,
+        command: &str,
+        profile: Option<(&str, &Path)>,
+        changes: impl IntoIterator<Item = Change>,
+    ) {
+        self.record_retracking(command, profile, changes, std::iter::empty());
+    }
+
+    /// Like `record`, for commands that also replace how `retracked`
+    /// variables are tracked without changing their values.
+    pub fn record_retracking<'a>(
+        &mut self,
+        command: &str,
+        profile: Option<(&str, &Path)>,
+        changes: impl IntoIterator<Item = Change>,
+        retracked: impl Iterator<Item = &'a String>,
+    ) {
+        let changes: Vec<Change> = changes.into_iter().collect();
+        let mut vars: BTreeSet<String> = retracked.cloned().collect();
+        vars.extend(changes.iter().map(|(var, _, _)| var.clone()));
+        let before = self.tracked_states(vars.iter());
+
+        if let Some(op) = self.append_journal(command, profile, &changes) {
+            self.undo_stack.push(UndoEntry { op, changes, tracked: before });
+            if self.undo_stack.len() > UNDO_LIMIT {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+        }
+    }
+
+    /// Revert the most recent undoable operation. Returns the variable
+    /// changes to apply to the shell, or `None` if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<Vec<Change>> {
+        let entry = self.undo_stack.pop()?;
+        let inverse: Vec<Change> = entry.changes.iter()
+            .rev()
+            .map(|(var, old, new)| (var.clone(), new.clone(), old.clone()))
+            .collect();
+
+        let after = self.tracked_states(entry.tracked.keys());
+        self.reinstate(entry.tracked);
+        self.redo_stack.push(UndoEntry { op: entry.op, changes: entry.changes, tracked: after });
+        self.append_journal("undo", None, &inverse);
+        Some(inverse)
+    }
+
+    /// Reapply the most recently undone operation. Returns the variable
+    /// changes to apply to the shell, or `None` if there is nothing to redo.
+    pub fn redo(&mut self) -> Option<Vec<Change>> {
+        let entry = self.redo_stack.pop()?;
+        let forward = entry.changes;
+
+        let before = self.tracked_states(entry.tracked.keys());
+        self.reinstate(entry.tracked);
+        self.undo_stack.push(UndoEntry { op: entry.op, changes: forward.clone(), tracked: before });
+        self.append_journal("redo", None, &forward);
+        Some(forward)
+    }
+
+    /// Write entries under a new operation number, redacting sensitive
+    /// values and dropping the oldest operations beyond `JOURNAL_LIMIT`.
+    /// Returns the number, or `None` if there were no changes.
+    fn append_journal(
+        &mut self,
+        command: &str,
+        profile: Option<(&str, &Path)>,
+        changes: &[Change],
+    ) -> Option<u32> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        let op = self.op_seq + 1;
+        let at = now_secs();
+        self.journal.extend(changes.iter().map(|(var, old, new)| JournalEntry {
+            op,
+            at,
+            command: command.to_string(),
+            profile: profile.map(|(name, _)| name.to_string()),
+            profile_path: profile.map(|(_, path)| path.display().to_string()),
+            var: var.clone(),
+            old: old.as_deref().map(|v| redact(var, v).to_string()),
+            new: new.as_deref().map(|v| redact(var, v).to_string()),
+        }));
+        self.op_seq = op;
+
+        let mut ops: Vec<u32> = self.journal.iter().map(|e| e.op).collect();
+        ops.dedup();
+        if ops.len() > JOURNAL_LIMIT {
+            let oldest = ops[ops.len() - JOURNAL_LIMIT];
+            self.journal.retain(|e| e.op >= oldest);
+        }
+        Some(op)
+    }
+
+    /// Current tracked state of the given variables.
+    fn tracked_states<'a>(
+        &self,
+        vars: impl Iterator<Item = &'a String>,
+    ) -> BTreeMap<String, Option<TrackedChange>> {
+        vars.map(|var| (var.clone(), self.tracked.get(var).cloned())).collect()
+    }
+
+    /// Put saved tracked states back in place.
+    fn reinstate(&mut self, states: BTreeMap<String, Option<TrackedChange>>) {
+        for (var, state) in states {
+            match state {
+                Some(change) => { self.tracked.insert(var, change); }
+                None => { self.tracked.remove(&var); }
+            }
         }
     }
 
@@ -148,16 +635,85 @@ impl Session {
         self.baseline.contains_key(var)
     }
 
+    /// Fingerprint of a value as stored in this session's baseline.
+    pub fn value_hash(&self, value: &str) -> u64 {
+        self.hasher.hash(value)
+    }
+
     /// Check if a baseline variable's value has changed.
     /// Compares current env value hash against stored baseline hash.
+    /// Always false while the baseline cannot be compared.
     pub fn baseline_changed(&self, var: &str, current_value: &str) -> bool {
+        if self.baseline_problem.is_some() {
+            return false;
+        }
         match self.baseline.get(var) {
-            Some(&baseline_hash) => hash_value(current_value) != baseline_hash,
+            Some(&baseline_hash) => self.value_hash(current_value) != baseline_hash,
             None => false,
         }
     }
 }
 
+/// Value a variable had before envision changed it: the previous value of
+/// its tracked change, or else its stored original.
+fn value_before<'a>(
+    tracked: &'a BTreeMap<String, TrackedChange>,
+    originals: Option<&'a BTreeMap<String, String>>,
+    var: &str,
+) -> Option<&'a str> {
+    match tracked.get(var) {
+        Some(TrackedChange::Set { previous, .. }) => previous.as_deref(),
+        Some(TrackedChange::Unset { previous, .. }) => Some(previous),
+        None => originals?.get(var).map(String::as_str),
+    }
+}
+
+/// Session data that could not be decoded. Commands that can run without
+/// tracking carry on and warn; the damaged data is never written back.
+#[derive(Debug)]
+pub struct Damage {
+    pub problem: String,
+    /// Session file the data was read from, when file storage is in use.
+    pub file_id: Option<String>,
+    pub salvaged: Salvage,
+}
+
+impl Damage {
+    /// Error text with recovery guidance. 00-R8
+    pub fn message(&self) -> String {
+        format!(
+            "{}. Run 'envision session repair' to start a fresh session from what can be salvaged.",
+            self.problem
+        )
+    }
+}
+
+/// Parts of a damaged session that could still be read. `None` means the
+/// part was lost, as opposed to present but empty.
+#[derive(Debug, Default)]
+pub struct Salvage {
+    pub tracked: Option<BTreeMap<String, TrackedChange>>,
+    pub snapshots: Option<Vec<Snapshot>>,
+    pub journal: Option<Vec<JournalEntry>>,
+}
+
+impl Salvage {
+    /// Names of the parts that could not be recovered.
+    pub fn lost(&self) -> Vec<&'static str> {
+        let mut lost = Vec::new();
+        if self.tracked.is_none() {
+            lost.push("tracked changes");
+        }
+        if self.snapshots.is_none() {
+            lost.push("snapshots");
+        }
+        if self.journal.is_none() {
+            lost.push("change log");
+        }
+        lost
+    }
+}
+
 pub struct SetResult {
     pub previous: Option<String>,
     pub overwrite_kind: Option<OverwriteKind>,
@@ -169,7 +725,6 @@ pub enum OverwriteKind {
 }
 
 pub struct UnsetResult {
-    pub previous: Option<String>,
     pub previous_kind: PreviousKind,
 }
 
@@ -179,62 +734,177 @@ pub enum PreviousKind {
     Untracked,
 }
 
+/// Linux MAX_ARG_STRLEN: exec fails with E2BIG if any single `NAME=value`
+/// environment string is longer than this, breaking every command in the shell.
+const MAX_ENV_STRING: usize = 128 * 1024;
+
+/// Typical ARG_MAX: combined size of arguments and environment for exec.
+const ARG_MAX: usize = 2 * 1024 * 1024;
+
+/// Whether an exported session payload stays under the per-variable limit.
+/// A larger one would make exec fail in the shell.
+pub fn fits_env(statement_len: usize) -> bool {
+    statement_len < MAX_ENV_STRING
+}
+
+/// What can be removed to make session data smaller.
+const SHRINK_HINT: &str = "Delete snapshots ('envision snapshot delete') or clear tracked changes \
+     ('envision clear'); the change log and undo history are capped on their own, and original \
+     values are only kept by sessions started with --full-baseline.";
+
+/// Notice for a session moved to file storage because it outgrew the
+/// environment.
+pub fn moved_to_file_warning(statement_len: usize, location: &str) -> String {
+    format!(
+        "Session data is {} KiB, too large for the {} KiB per-variable limit, so it is now \
+         stored in {location}. {SHRINK_HINT}",
+        statement_len / 1024,
+        MAX_ENV_STRING / 1024
+    )
+}
+
+/// Warn when an exported session payload is approaching the kernel's limits
+/// on a single environment string or on the whole environment.
+/// `statement_len` is the length of the export statement, which slightly
+/// overestimates the resulting `NAME=value` string.
+pub fn env_size_warning(statement_len: usize) -> Option<String> {
+    let len = statement_len;
+    if len >= MAX_ENV_STRING / 2 {
+        return Some(format!(
+            "Session data is {} KiB, over half the {} KiB per-variable limit; past it, the \
+             session moves to a file. {SHRINK_HINT}",
+            len / 1024,
+            MAX_ENV_STRING / 1024
+        ));
+    }
+
+    let others: usize = std::env::vars_os()
+        .filter(|(k, _)| k != SESSION_VAR)
+        .map(|(k, v)| k.len() + v.len() + 2)
+        .sum();
+    if others + len >= ARG_MAX * 3 / 4 {
+        return Some(format!(
+            "Environment is {} KiB including {} KiB of session data, close to the {} KiB exec limit.",
+            (others + len) / 1024,
+            len / 1024,
+            ARG_MAX / 1024
+        ));
+    }
+    None
+}
+
 /// Env vars managed by envision that should be excluded from untracked change detection.
 const ENVISION_VARS: &[&str] = &[
     SESSION_VAR,
     "ENVISION_PROFILE",
     "ENVISION_PROFILE_CHECKSUM",
     "ENVISION_SESSION_ID",
+    "ENVISION_SESSION_NAME",
     "ENVISION_TRACKED",
     "ENVISION_DIRTY",
+    owner::SHELL_PID_VAR,
 ];
 
-/// Count environment changes not tracked by the session.
-/// An untracked change is a baseline variable whose current hash differs
-/// from the stored hash, AND which is not in the tracked set.
-/// Also counts baseline variables that have disappeared and new variables
-/// that weren't in the baseline (excluding envision-managed vars and tracked vars).
-pub fn count_untracked(session: &Session, current_env: &BTreeMap<String, String>) -> usize {
-    let mut count = 0;
+/// An environment change the session did not make.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UntrackedChange {
+    /// Set now, absent from the baseline.
+    Added(String),
+    /// In the baseline, gone now.
+    Removed(String),
+    /// In the baseline with a different value.
+    Modified(String),
+}
 
-    // Check baseline vars for hash changes or disappearance
-    for (var, &baseline_hash) in &session.baseline {
-        if session.tracked.contains_key(var) {
-            continue;
-        }
-        match current_env.get(var) {
-            Some(current_val) => {
-                if hash_value(current_val) != baseline_hash {
-                    count += 1;
-                }
-            }
-            None => {
-                count += 1;
-            }
+impl UntrackedChange {
+    pub fn var(&self) -> &str {
+        match self {
+            UntrackedChange::Added(var) | UntrackedChange::Removed(var) | UntrackedChange::Modified(var) => var,
         }
     }
+}
+
+/// List environment changes not tracked by the session, sorted by name:
+/// baseline variables whose current hash differs from the stored hash or
+/// that have disappeared, and new variables that weren't in the baseline.
+/// Tracked variables, envision-managed ones and those matching `ignore` are
+/// left out. 02-R5: the session is dirty when the list is not empty.
+pub fn list_untracked(
+    session: &Session,
+    current_env: &BTreeMap<String, String>,
+    ignore: &Ignore,
+) -> Vec<UntrackedChange> {
+    let skip = |var: &str| session.tracked.contains_key(var) || is_envision_var(var) || ignore.is_ignored(var);
+
+    // Check baseline vars for hash changes or disappearance
+    let mut changes: Vec<UntrackedChange> = session.baseline.keys()
+        .filter(|var| !skip(var))
+        .filter_map(|var| match current_env.get(var) {
+            Some(value) if session.baseline_changed(var, value) => Some(UntrackedChange::Modified(var.clone())),
+            Some(_) => None,
+            None => Some(UntrackedChange::Removed(var.clone())),
+        })
+        .collect();
 
     // Check for new variables not in baseline and not tracked
-    for var in current_env.keys() {
-        if ENVISION_VARS.iter().any(|&v| v == var) {
-            continue;
-        }
-        if session.baseline.contains_key(var) {
-            continue;
-        }
-        if session.tracked.contains_key(var) {
-            continue;
-        }
-        count += 1;
-    }
+    changes.extend(current_env.keys()
+        .filter(|var| !skip(var) && !session.in_baseline(var))
+        .map(|var| UntrackedChange::Added(var.clone())));
+
+    changes.sort_by(|a, b| a.var().cmp(b.var()));
+    changes
+}
+
+/// Hash every variable except the session payload itself.
+fn capture_baseline(env: &BTreeMap<String, String>, hasher: &Hasher) -> BTreeMap<String, u64> {
+    env.iter()
+        .filter(|(k, _)| k.as_str() != SESSION_VAR)
+        .map(|(k, v)| (k.clone(), hasher.hash(v)))
+        .collect()
+}
+
+/// Count variables whose value differs from `baseline`, tracked or not:
+/// modified, removed and added. Envision's own variables and those matching
+/// `ignore` are left out.
+pub fn count_changed(
+    baseline: &BTreeMap<String, u64>,
+    hasher: &Hasher,
+    current_env: &BTreeMap<String, String>,
+    ignore: &Ignore,
+) -> usize {
+    let counted = |var: &str| !is_envision_var(var) && !ignore.is_ignored(var);
+    let changed = baseline.iter()
+        .filter(|(var, _)| counted(var))
+        .filter(|(var, hash)| current_env.get(*var).is_none_or(|v| hasher.hash(v) != **hash))
+        .count();
+    let added = current_env.keys()
+        .filter(|var| counted(var) && !baseline.contains_key(*var))
+        .count();
+    changed + added
+}
+
+/// Variables managed by envision itself, never reported as changes.
+pub fn is_envision_var(var: &str) -> bool {
+    ENVISION_VARS.contains(&var)
+}
 
-    count
+/// Current time as seconds since the Unix epoch.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before epoch")
+        .as_secs()
 }
 
-/// FNV-1a hash for value fingerprinting.
+/// FNV-1a hash of a string: profile checksums and pre-v5 baselines.
 pub fn hash_value(s: &str) -> u64 {
+    hash_bytes(s.as_bytes())
+}
+
+/// FNV-1a over raw bytes. Also the session payload's corruption check.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
     let mut h: u64 = 0xcbf29ce484222325;
-    for b in s.as_bytes() {
+    for b in bytes {
         h ^= *b as u64;
         h = h.wrapping_mul(0x100000001b3);
     }
@@ -268,19 +938,69 @@ pub fn is_critical_var(name: &str) -> bool {
     CRITICAL_VARS.contains(&name)
 }
 
-fn generate_session_id(pid: u32, timestamp: u64) -> String {
-    let mut h: u64 = 0x517cc1b727220a95;
-    h ^= pid as u64;
-    h = h.wrapping_mul(0x9e3779b97f4a7c15);
-    h ^= timestamp;
-    h = h.wrapping_mul(0x9e3779b97f4a7c15);
-    h ^= h >> 27;
-    format!("{:08x}", (h >> 32) as u32)
+/// Check if a variable name looks like it holds a secret.
+pub fn is_sensitive_var(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    SENSITIVE_MARKERS.iter().any(|m| upper.contains(m))
+}
+
+/// `value`, or `REDACTED` if `var` is sensitive.
+pub fn redact<'a>(var: &str, value: &'a str) -> &'a str {
+    if is_sensitive_var(var) { REDACTED } else { value }
+}
+
+/// Label for `session init --name`: shown in the banner and exported, so
+/// keep it short and printable.
+pub fn validate_session_name(name: &str) -> Result<(), String> {
+    const MAX_LEN: usize = 40;
+
+    if name.trim().is_empty() {
+        return Err("Session name cannot be empty".into());
+    }
+    if name.chars().count() > MAX_LEN {
+        return Err(format!("Session name '{name}' is longer than {MAX_LEN} characters"));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("Session name cannot contain control characters".into());
+    }
+    Ok(())
+}
+
+/// Session identifier: the short hostname and 64 random bits, such as
+/// `buildhost-3f9c0a1b2c3d4e5f`. The host keeps sessions from separate
+/// machines or containers apart in shared storage.
+pub fn generate_session_id() -> Result<String, String> {
+    let random = u64::from_le_bytes(
+        fingerprint::random_bytes().map_err(|e| format!("Cannot generate a session ID: {e}"))?,
+    );
+    let host = owner::hostname().unwrap_or_default();
+    let host: String = host.split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .take(16)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    Ok(match host.trim_matches('-') {
+        "" => format!("{random:016x}"),
+        host => format!("{host}-{random:016x}"),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testutil::env;
+
+    /// Session hashing with FNV-1a, for baselines built by hand.
+    fn fnv_session() -> Session {
+        Session {
+            fingerprint: Scheme::Fnv1a,
+            hasher: Hasher::Fnv1a,
+            ..Session::new(&BTreeMap::new()).unwrap()
+        }
+    }
 
     fn test_env() -> BTreeMap<String, String> {
         let mut env = BTreeMap::new();
@@ -292,53 +1012,76 @@ mod tests {
     #[test]
     fn new_session_hashes_baseline() {
         let env = test_env();
-        let session = Session::new(&env);
+        let session = Session::new(&env).unwrap();
         assert_eq!(session.baseline.len(), 2);
-        assert_eq!(*session.baseline.get("FOO").unwrap(), hash_value("bar"));
+        assert_eq!(*session.baseline.get("FOO").unwrap(), session.value_hash("bar"));
         assert!(session.tracked.is_empty());
     }
 
     #[test]
     fn encode_decode_roundtrip() {
         let env = test_env();
-        let session = Session::new(&env);
+        let session = Session::new(&env).unwrap();
         let encoded = session.encode().unwrap();
         let decoded = Session::decode(&encoded).unwrap();
         assert_eq!(session.id, decoded.id);
         assert_eq!(session.baseline, decoded.baseline);
     }
 
+    #[test]
+    fn oversized_payload_does_not_fit() {
+        assert!(fits_env(1024));
+        assert!(!fits_env(MAX_ENV_STRING));
+    }
+
+    #[test]
+    fn large_payload_warns() {
+        assert!(env_size_warning(MAX_ENV_STRING / 2).is_some());
+    }
+
     #[test]
     fn baseline_excludes_session_var() {
         let mut env = test_env();
         env.insert(SESSION_VAR.into(), "should_be_excluded".into());
-        let session = Session::new(&env);
+        let session = Session::new(&env).unwrap();
         assert!(!session.baseline.contains_key(SESSION_VAR));
     }
 
     #[test]
     fn baseline_changed_detection() {
         let env = test_env();
-        let session = Session::new(&env);
+        let session = Session::new(&env).unwrap();
         assert!(!session.baseline_changed("FOO", "bar"));
         assert!(session.baseline_changed("FOO", "baz"));
         assert!(!session.baseline_changed("NONEXISTENT", "whatever"));
     }
 
     #[test]
-    fn session_id_is_8_hex_chars() {
-        let id = generate_session_id(1234, 1700000000);
-        assert_eq!(id.len(), 8);
-        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    fn session_id_names_host_and_64_random_bits() {
+        let id = generate_session_id().unwrap();
+        let random = &id[id.len() - 16..];
+        assert!(random.chars().all(|c| c.is_ascii_hexdigit()));
+        match id.len() {
+            16 => {}
+            len => assert_eq!(&id[len - 17..len - 16], "-"),
+        }
+        // Usable as a session file name
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    }
+
+    #[test]
+    fn ids_do_not_repeat() {
+        let ids: std::collections::BTreeSet<String> = (0..100).map(|_| generate_session_id().unwrap()).collect();
+        assert_eq!(ids.len(), 100);
     }
 
     #[test]
-    fn different_inputs_produce_different_ids() {
-        let a = generate_session_id(1234, 1700000000);
-        let b = generate_session_id(1235, 1700000000);
-        let c = generate_session_id(1234, 1700000001);
-        assert_ne!(a, b);
-        assert_ne!(a, c);
+    fn session_names() {
+        assert!(validate_session_name("release-debug").is_ok());
+        assert!(validate_session_name("fix #42 (staging)").is_ok());
+        assert!(validate_session_name("  ").is_err());
+        assert!(validate_session_name("a\nb").is_err());
+        assert!(validate_session_name(&"x".repeat(41)).is_err());
     }
 
     #[test]
@@ -367,21 +1110,21 @@ mod tests {
 
     #[test]
     fn track_set_new_variable() {
-        let session = &mut Session::new(&BTreeMap::new());
-        let result = session.track_set("FOO", "bar");
+        let session = &mut Session::new(&BTreeMap::new()).unwrap();
+        let result = session.track_set("FOO", "bar", None, SourceKind::Set);
         assert!(result.previous.is_none());
         assert!(result.overwrite_kind.is_none());
         assert!(matches!(
             session.tracked.get("FOO"),
-            Some(TrackedChange::Set { value, previous: None }) if value == "bar"
+            Some(TrackedChange::Set { value, previous: None, .. }) if value == "bar"
         ));
     }
 
     #[test]
     fn track_set_overwrites_tracked() {
-        let session = &mut Session::new(&BTreeMap::new());
-        session.track_set("FOO", "first");
-        let result = session.track_set("FOO", "second");
+        let session = &mut Session::new(&BTreeMap::new()).unwrap();
+        session.track_set("FOO", "first", None, SourceKind::Set);
+        let result = session.track_set("FOO", "second", None, SourceKind::Set);
         assert_eq!(result.previous.as_deref(), Some("first"));
         assert!(matches!(result.overwrite_kind, Some(OverwriteKind::Tracked)));
     }
@@ -389,36 +1132,298 @@ mod tests {
     #[test]
     fn track_unset_original_variable() {
         let env = test_env();
-        let session = &mut Session::new(&env);
-        // Simulate: var exists in baseline, we need to supply previous value
-        // via a prior track_set or by passing it in from the real env
-        // Since baseline only has hashes, track_unset won't know the value
-        // unless it was tracked. For original vars, the caller passes the value.
-        let result = session.track_unset("FOO");
-        // previous is None because tracked_value returns None for untracked vars
-        assert!(result.previous.is_none());
+        let session = &mut Session::new(&env).unwrap();
+        // Hash-only baseline: the caller supplies the value from the real env
+        let result = session.track_unset("FOO", Some("bar"), SourceKind::Unset);
         assert!(matches!(result.previous_kind, PreviousKind::Original));
+        assert!(matches!(
+            session.tracked.get("FOO"),
+            Some(TrackedChange::Unset { previous, .. }) if previous == "bar"
+        ));
+    }
+
+    #[test]
+    fn full_baseline_keeps_values_except_sensitive() {
+        let mut env = test_env();
+        env.insert("GITHUB_TOKEN".into(), "s3cret".into());
+        let mut session = Session::new(&env).unwrap();
+        session.capture_originals(&env);
+        assert_eq!(session.original_value("FOO"), Some("bar"));
+        assert_eq!(session.original_value("GITHUB_TOKEN"), None);
+        assert!(session.in_baseline("GITHUB_TOKEN"));
+    }
+
+    #[test]
+    fn track_set_over_original_records_previous() {
+        let env = test_env();
+        let session = &mut Session::new(&env).unwrap();
+        session.capture_originals(&env);
+        let result = session.track_set("PATH", "/opt/bin", None, SourceKind::Set);
+        assert_eq!(result.previous.as_deref(), Some("/usr/bin"));
+        assert!(matches!(
+            session.tracked.get("PATH"),
+            Some(TrackedChange::Set { previous: Some(p), .. }) if p == "/usr/bin"
+        ));
+    }
+
+    #[test]
+    fn track_set_on_hash_only_baseline_keeps_current_value() {
+        let env = test_env();
+        let session = &mut Session::new(&env).unwrap();
+        session.track_set("PATH", "/opt/bin", Some("/usr/bin"), SourceKind::Set);
+        session.track_set("PATH", "/x", Some("/opt/bin"), SourceKind::Set);
+        assert!(matches!(
+            session.tracked.get("PATH"),
+            Some(TrackedChange::Set { previous: Some(p), .. }) if p == "/usr/bin"
+        ));
+    }
+
+    #[test]
+    fn repeated_set_keeps_original_previous() {
+        let env = test_env();
+        let session = &mut Session::new(&env).unwrap();
+        session.capture_originals(&env);
+        session.track_set("FOO", "first", None, SourceKind::Set);
+        session.track_set("FOO", "second", None, SourceKind::Set);
+        assert!(matches!(
+            session.tracked.get("FOO"),
+            Some(TrackedChange::Set { previous: Some(p), .. }) if p == "bar"
+        ));
+    }
+
+    #[test]
+    fn track_unset_original_with_full_baseline() {
+        let env = test_env();
+        let session = &mut Session::new(&env).unwrap();
+        session.capture_originals(&env);
+        session.track_unset("FOO", None, SourceKind::Unset);
+        assert!(matches!(
+            session.tracked.get("FOO"),
+            Some(TrackedChange::Unset { previous, .. }) if previous == "bar"
+        ));
+    }
+
+    #[test]
+    fn original_survives_unset_after_set() {
+        let env = test_env();
+        let session = &mut Session::new(&env).unwrap();
+        session.capture_originals(&env);
+        session.track_set("FOO", "changed", None, SourceKind::Set);
+        session.track_unset("FOO", Some("changed"), SourceKind::Unset);
+        assert!(matches!(
+            session.tracked.get("FOO"),
+            Some(TrackedChange::Unset { previous, .. }) if previous == "bar"
+        ));
+    }
+
+    #[test]
+    fn tracked_changes_record_their_source() {
+        let session = &mut Session::new(&test_env()).unwrap();
+        session.track_set("FOO", "a", None, SourceKind::Set);
+        session.track_set("FOO", "b", None, SourceKind::Import { session: "abcd1234".into() });
+        let source = session.tracked["FOO"].source().unwrap();
+        assert_eq!(source.kind, SourceKind::Import { session: "abcd1234".into() });
+        assert!(source.at > 0);
+
+        let json = serde_json::to_value(&session.tracked["FOO"]).unwrap();
+        assert_eq!(json["Set"]["source"]["by"], "import");
+
+        // Changes tracked before sources were recorded
+        let legacy: TrackedChange = serde_json::from_str(r#"{"Unset":{"previous":"x"}}"#).unwrap();
+        assert!(legacy.source().is_none());
+    }
+
+    #[test]
+    fn snapshots_get_sequence_numbers() {
+        let env = test_env();
+        let mut session = Session::new(&env).unwrap();
+        session.save_snapshot(None, &env).unwrap();
+        session.save_snapshot(Some("named"), &env).unwrap();
+        assert_eq!(session.find_snapshot("1").unwrap().seq, 1);
+        assert_eq!(session.find_snapshot("named").unwrap().seq, 2);
+
+        session.remove_snapshot("1").unwrap();
+        let next = session.save_snapshot(None, &env).unwrap();
+        assert_eq!(next.seq, 3);
+    }
+
+    #[test]
+    fn auto_snapshots_pruned_beyond_limit() {
+        let env = test_env();
+        let mut session = Session::new(&env).unwrap();
+        session.save_snapshot(Some("keep"), &env).unwrap();
+        for i in 0..4 {
+            session.auto_snapshot(&format!("before set V{i}"), &env, 2);
+        }
+        let seqs: Vec<u32> = session.snapshots.iter().map(|s| s.seq).collect();
+        assert_eq!(seqs, vec![1, 4, 5]);
+        assert_eq!(session.snapshots[2].label(), "#5 (auto: before set V3)");
+    }
+
+    #[test]
+    fn auto_snapshot_limit_zero_keeps_none() {
+        let env = test_env();
+        let mut session = Session::new(&env).unwrap();
+        session.auto_snapshot("before clear", &env, 0);
+        assert!(session.snapshots.is_empty());
+    }
+
+    #[test]
+    fn duplicate_snapshot_name_rejected() {
+        let env = test_env();
+        let mut session = Session::new(&env).unwrap();
+        session.save_snapshot(Some("dup"), &env).unwrap();
+        assert!(session.save_snapshot(Some("dup"), &env).is_err());
+    }
+
+    #[test]
+    fn record_groups_changes_by_operation() {
+        let mut session = Session::new(&BTreeMap::new()).unwrap();
+        session.record("set", None, [("FOO".into(), None, Some("1".into()))]);
+        session.record("profile", Some(("dev", Path::new("dev.sh"))), [
+            ("A".into(), None, Some("a".into())),
+            ("B".into(), Some("b".into()), None),
+        ]);
+        session.record("clear", None, []);
+
+        let ops: Vec<u32> = session.journal.iter().map(|e| e.op).collect();
+        assert_eq!(ops, vec![1, 2, 2]);
+        assert_eq!(session.op_seq, 2);
+        assert_eq!(session.journal[1].profile.as_deref(), Some("dev"));
+    }
+
+    #[test]
+    fn undo_reverts_values_and_tracking() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.record("set", None, [("NEW".into(), None, Some("1".into()))]);
+        session.track_set("NEW", "1", None, SourceKind::Set);
+
+        let changes = session.undo().unwrap();
+        assert_eq!(changes, vec![("NEW".to_string(), Some("1".to_string()), None)]);
+        assert!(!session.tracked.contains_key("NEW"));
+        assert!(session.undo().is_none());
+        assert_eq!(session.journal.last().unwrap().command, "undo");
+    }
+
+    #[test]
+    fn redo_reapplies_undone_operation() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.record("set", None, [("NEW".into(), None, Some("1".into()))]);
+        session.track_set("NEW", "1", None, SourceKind::Set);
+        session.undo().unwrap();
+
+        let changes = session.redo().unwrap();
+        assert_eq!(changes, vec![("NEW".to_string(), None, Some("1".to_string()))]);
+        assert!(matches!(
+            session.tracked.get("NEW"),
+            Some(TrackedChange::Set { value, .. }) if value == "1"
+        ));
+        assert!(session.redo().is_none());
+        assert_eq!(session.undo_stack.len(), 1);
+    }
+
+    #[test]
+    fn undo_restores_tracking_of_retracked_vars() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.track_set("KEPT", "1", None, SourceKind::Set);
+        let retracked = ["KEPT".to_string()];
+        session.record_retracking("snapshot restore", None, [("NEW".into(), None, Some("1".into()))], retracked.iter());
+        session.tracked.clear();
+
+        session.undo().unwrap();
+        assert!(session.tracked.contains_key("KEPT"));
+        session.redo().unwrap();
+        assert!(!session.tracked.contains_key("KEPT"));
+    }
+
+    #[test]
+    fn new_operation_clears_redo() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.record("set", None, [("A".into(), None, Some("1".into()))]);
+        session.undo().unwrap();
+        session.record("set", None, [("B".into(), None, Some("2".into()))]);
+        assert!(session.redo().is_none());
+    }
+
+    #[test]
+    fn undo_multi_var_operation_in_reverse() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.record("profile", Some(("dev", Path::new("dev.sh"))), [
+            ("A".into(), None, Some("1".into())),
+            ("B".into(), Some("old".into()), Some("2".into())),
+        ]);
+        let changes = session.undo().unwrap();
+        assert_eq!(changes[0].0, "B");
+        assert_eq!(changes[0].2.as_deref(), Some("old"));
+        assert_eq!(changes[1].0, "A");
+        assert_eq!(changes[1].2, None);
+    }
+
+    #[test]
+    fn journal_keeps_newest_operations() {
+        let mut session = Session::new(&test_env()).unwrap();
+        for i in 0..JOURNAL_LIMIT + 20 {
+            session.record("set", None, [("N".into(), Some(i.to_string()), Some((i + 1).to_string()))]);
+        }
+        assert_eq!(session.journal.len(), JOURNAL_LIMIT);
+        assert_eq!(session.journal[0].op, 21);
+        assert_eq!(session.op_seq as usize, JOURNAL_LIMIT + 20);
+
+        // Undo no longer needs the journal for its changes
+        let changes = session.undo().unwrap();
+        assert_eq!(changes, vec![("N".to_string(), Some("120".to_string()), Some("119".to_string()))]);
+        assert_eq!(session.journal.len(), JOURNAL_LIMIT);
+    }
+
+    #[test]
+    fn journal_redacts_sensitive_values() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.record("set", None, [("API_TOKEN".into(), Some("old".into()), Some("new".into()))]);
+        let entry = &session.journal[0];
+        assert_eq!((entry.old.as_deref(), entry.new.as_deref()), (Some(REDACTED), Some(REDACTED)));
+
+        let changes = session.undo().unwrap();
+        assert_eq!(changes[0].2.as_deref(), Some("old"));
+        assert_eq!(session.journal[1].new.as_deref(), Some(REDACTED));
+    }
+
+    #[test]
+    fn sensitive_var_detection() {
+        assert!(is_sensitive_var("AWS_SECRET_ACCESS_KEY"));
+        assert!(is_sensitive_var("npm_token"));
+        assert!(!is_sensitive_var("PATH"));
     }
 
     #[test]
     fn track_unset_tracked_variable() {
-        let session = &mut Session::new(&BTreeMap::new());
-        session.track_set("FOO", "bar");
-        let result = session.track_unset("FOO");
-        assert_eq!(result.previous.as_deref(), Some("bar"));
+        let session = &mut Session::new(&BTreeMap::new()).unwrap();
+        session.track_set("FOO", "bar", None, SourceKind::Set);
+        let result = session.track_unset("FOO", Some("bar"), SourceKind::Unset);
         assert!(matches!(result.previous_kind, PreviousKind::Tracked));
+        // Added and removed through the tool: nothing left to revert
+        assert!(!session.tracked.contains_key("FOO"));
+    }
+
+    #[test]
+    fn track_unset_untracked_variable() {
+        let session = &mut Session::new(&BTreeMap::new()).unwrap();
+        let result = session.track_unset("EXTERNAL", Some("from_script"), SourceKind::Unset);
+        assert!(matches!(result.previous_kind, PreviousKind::Untracked));
+        assert!(matches!(
+            session.tracked.get("EXTERNAL"),
+            Some(TrackedChange::Unset { previous, .. }) if previous == "from_script"
+        ));
     }
 
     #[test]
     fn track_unset_nonexistent_variable() {
-        let session = &mut Session::new(&BTreeMap::new());
-        let result = session.track_unset("FOO");
-        assert!(result.previous.is_none());
+        let session = &mut Session::new(&BTreeMap::new()).unwrap();
+        session.track_unset("FOO", None, SourceKind::Unset);
         assert!(!session.tracked.contains_key("FOO"));
     }
 
     #[test]
-    fn count_untracked_clean_when_matching() {
+    fn list_untracked_clean_when_matching() {
         let mut baseline = BTreeMap::new();
         baseline.insert("FOO".into(), hash_value("bar"));
         baseline.insert("BAZ".into(), hash_value("qux"));
@@ -428,17 +1433,18 @@ mod tests {
             created_at: 0,
             baseline,
             tracked: BTreeMap::new(),
+            ..fnv_session()
         };
 
         let mut env = BTreeMap::new();
         env.insert("FOO".into(), "bar".into());
         env.insert("BAZ".into(), "qux".into());
 
-        assert_eq!(count_untracked(&session, &env), 0);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 0);
     }
 
     #[test]
-    fn count_untracked_detects_changed_value() {
+    fn list_untracked_detects_changed_value() {
         let mut baseline = BTreeMap::new();
         baseline.insert("FOO".into(), hash_value("bar"));
 
@@ -447,16 +1453,17 @@ mod tests {
             created_at: 0,
             baseline,
             tracked: BTreeMap::new(),
+            ..fnv_session()
         };
 
         let mut env = BTreeMap::new();
         env.insert("FOO".into(), "changed".into());
 
-        assert_eq!(count_untracked(&session, &env), 1);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 1);
     }
 
     #[test]
-    fn count_untracked_detects_removed_var() {
+    fn list_untracked_detects_removed_var() {
         let mut baseline = BTreeMap::new();
         baseline.insert("FOO".into(), hash_value("bar"));
 
@@ -465,35 +1472,52 @@ mod tests {
             created_at: 0,
             baseline,
             tracked: BTreeMap::new(),
+            ..fnv_session()
         };
 
         let env = BTreeMap::new();
 
-        assert_eq!(count_untracked(&session, &env), 1);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 1);
     }
 
     #[test]
-    fn count_untracked_detects_new_var() {
+    fn list_untracked_detects_new_var() {
         let session = Session {
             id: "test".into(),
             created_at: 0,
             baseline: BTreeMap::new(),
             tracked: BTreeMap::new(),
+            ..Session::new(&BTreeMap::new()).unwrap()
         };
 
         let mut env = BTreeMap::new();
         env.insert("NEW_VAR".into(), "hello".into());
 
-        assert_eq!(count_untracked(&session, &env), 1);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 1);
+    }
+
+    #[test]
+    fn list_untracked_names_each_change() {
+        let session = Session::new(&test_env()).unwrap();
+        let mut env = BTreeMap::new();
+        env.insert("FOO".into(), "changed".into());
+        env.insert("NEW".into(), "1".into());
+
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()), [
+            UntrackedChange::Modified("FOO".into()),
+            UntrackedChange::Added("NEW".into()),
+            UntrackedChange::Removed("PATH".into()),
+        ]);
     }
 
     #[test]
-    fn count_untracked_ignores_envision_vars() {
+    fn list_untracked_ignores_envision_vars() {
         let session = Session {
             id: "test".into(),
             created_at: 0,
             baseline: BTreeMap::new(),
             tracked: BTreeMap::new(),
+            ..Session::new(&BTreeMap::new()).unwrap()
         };
 
         let mut env = BTreeMap::new();
@@ -503,11 +1527,22 @@ mod tests {
         env.insert("ENVISION_SESSION_ID".into(), "abc".into());
         env.insert("ENVISION_TRACKED".into(), "0".into());
 
-        assert_eq!(count_untracked(&session, &env), 0);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 0);
+    }
+
+    #[test]
+    fn list_untracked_skips_ignored_vars() {
+        let session = Session::new(&BTreeMap::from([("PWD".to_string(), "/home".to_string())])).unwrap();
+        let env = BTreeMap::from([
+            ("PWD".to_string(), "/tmp".to_string()),
+            ("OLDPWD".to_string(), "/home".to_string()),
+            ("STARSHIP_CMD_STATUS".to_string(), "0".to_string()),
+        ]);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 0);
     }
 
     #[test]
-    fn count_untracked_skips_tracked_vars() {
+    fn list_untracked_skips_tracked_vars() {
         let mut baseline = BTreeMap::new();
         baseline.insert("FOO".into(), hash_value("bar"));
 
@@ -515,6 +1550,7 @@ mod tests {
         tracked.insert("FOO".into(), TrackedChange::Set {
             value: "changed".into(),
             previous: Some("bar".into()),
+            source: None,
         });
 
         let session = Session {
@@ -522,11 +1558,80 @@ mod tests {
             created_at: 0,
             baseline,
             tracked,
+            ..fnv_session()
         };
 
         let mut env = BTreeMap::new();
         env.insert("FOO".into(), "changed".into());
 
-        assert_eq!(count_untracked(&session, &env), 0);
+        assert_eq!(list_untracked(&session, &env, &Ignore::defaults()).len(), 0);
+    }
+
+    #[test]
+    fn keyed_baseline_survives_decode() {
+        let session = Session::new(&test_env()).unwrap();
+        assert!(matches!(session.fingerprint, Scheme::Keyed { .. }));
+        assert_ne!(session.baseline["FOO"], hash_value("bar"));
+
+        let decoded = Session::decode(&session.encode().unwrap()).unwrap();
+        assert!(!decoded.baseline_changed("FOO", "bar"));
+        assert!(decoded.baseline_changed("FOO", "other"));
+    }
+
+    #[test]
+    fn replaced_key_leaves_session_usable() {
+        let mut session = Session::new(&test_env()).unwrap();
+        session.track_set("NEW", "1", None, SourceKind::Set);
+        let Scheme::Keyed { salt, check } = session.fingerprint else { panic!("expected a keyed scheme") };
+        session.fingerprint = Scheme::Keyed { salt, check: check ^ 1 };
+
+        let mut decoded = Session::decode(&session.encode().unwrap()).unwrap();
+        assert!(decoded.baseline_problem.as_deref().unwrap().contains("missing or was replaced"));
+        assert!(decoded.tracked.contains_key("NEW"));
+        assert!(!decoded.baseline_changed("FOO", "other"));
+
+        // Rebasing starts over with a working key
+        decoded.rebase(&test_env());
+        assert!(decoded.baseline_problem.is_none());
+        assert!(decoded.baseline_changed("FOO", "other"));
+        assert!(!decoded.baseline_changed("FOO", "bar"));
+    }
+
+    #[test]
+    fn child_session_measures_changes_per_layer() {
+        let root = Session::new(&env(&[("HOME", "/home/u"), ("PATH", "/bin")])).unwrap();
+
+        // Parent shell changed PATH before the nested shell started
+        let child = Session::new_child(&root, &env(&[("HOME", "/home/u"), ("PATH", "/opt:/bin")])).unwrap();
+        assert_eq!(child.root_id(), root.id);
+        assert_eq!(child.original_value("PATH"), Some("/opt:/bin"));
+
+        // The nested shell then adds a variable of its own
+        let now = env(&[("HOME", "/home/u"), ("PATH", "/opt:/bin"), ("FOO", "1")]);
+        assert_eq!(count_changed(&child.baseline, &child.hasher, &now, &Ignore::defaults()), 1);
+        assert_eq!(count_changed(&child.root_baseline(), &child.hasher, &now, &Ignore::defaults()), 2);
+
+        // A grandchild still measures against the top-level session
+        let grandchild = Session::new_child(&child, &now).unwrap();
+        assert_eq!(grandchild.root_id(), root.id);
+        assert_eq!(count_changed(&grandchild.root_baseline(), &grandchild.hasher, &now, &Ignore::defaults()), 2);
+    }
+
+    #[test]
+    fn rebase_accepts_untracked_changes_and_keeps_tracked() {
+        let start = env(&[("PATH", "/bin"), ("NVM_DIR", "")]);
+        let mut session = Session::new(&start).unwrap();
+        session.capture_originals(&start);
+        session.track_set("FOO", "1", None, SourceKind::Set);
+
+        let now = env(&[("PATH", "/nvm:/bin"), ("NVM_DIR", "/nvm"), ("FOO", "1")]);
+        assert_eq!(list_untracked(&session, &now, &Ignore::defaults()).len(), 2);
+
+        session.rebase(&now);
+        assert_eq!(list_untracked(&session, &now, &Ignore::defaults()).len(), 0);
+        assert_eq!(session.original_value("PATH"), Some("/nvm:/bin"));
+        // Still known as added through envision, so unset drops it again
+        assert!(!session.in_baseline("FOO"));
+        assert!(session.tracked.contains_key("FOO"));
     }
 }
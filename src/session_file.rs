@@ -0,0 +1,177 @@
+use crate::fingerprint::Scheme;
+use crate::schema;
+use crate::session::{self, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Identifies an exported session document. 00-F01
+pub const FORMAT: &str = "envision-session";
+
+/// Version of the document envelope written by this build. The session
+/// inside carries its own schema version and is migrated on import.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A session saved to a file to be shared or imported into another shell.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionFile {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: u64,
+    /// Profile loaded in the exporting shell, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileInfo>,
+    /// The session in the schema version it was written with.
+    pub session: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+impl SessionFile {
+    /// Package a session for export. Only the tracked changes travel: the
+    /// rest describes the exporting shell. Snapshots and full-baseline
+    /// originals hold its environment, the owner names its process, and the
+    /// baseline hashes are keyed with a secret that only exists there.
+    pub fn new(session: &Session, profile: Option<ProfileInfo>) -> Result<Self, String> {
+        let mut session = session.clone();
+        session.snapshots.clear();
+        session.journal.clear();
+        session.undo_stack.clear();
+        session.redo_stack.clear();
+        session.originals = None;
+        session.owner = None;
+        session.baseline.clear();
+        session.fingerprint = Scheme::default();
+
+        Ok(Self {
+            format: FORMAT.to_string(),
+            version: FORMAT_VERSION,
+            exported_at: session::now_secs(),
+            profile,
+            session: serde_json::to_value(&session)
+                .map_err(|e| format!("Failed to serialize session: {e}"))?,
+        })
+    }
+
+    /// Write the document with owner-only permissions. Refuses to replace an
+    /// existing file unless `overwrite` is set.
+    pub fn write(&self, path: &Path, overwrite: bool) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize session: {e}"))?;
+
+        let mut options = OpenOptions::new();
+        options.write(true).mode(0o600);
+        if overwrite {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+
+        let mut file = options.open(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                format!("{} already exists. Use --force to overwrite it.", path.display())
+            }
+            _ => format!("Cannot write {}: {e}", path.display()),
+        })?;
+        file.write_all(json.as_bytes())
+            .and_then(|_| file.write_all(b"\n"))
+            .map_err(|e| format!("Cannot write {}: {e}", path.display()))
+    }
+
+    /// Read and check an exported session document.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: Self = serde_json::from_str(text)
+            .map_err(|e| format!("not an exported envision session ({e})"))?;
+        if file.format != FORMAT {
+            return Err(format!("not an exported envision session (format '{}')", file.format));
+        }
+        if file.version > FORMAT_VERSION {
+            return Err(format!(
+                "exported by a newer envision (format v{}, this build reads up to v{FORMAT_VERSION})",
+                file.version
+            ));
+        }
+        Ok(file)
+    }
+
+    /// The exported session, migrated to the current schema.
+    pub fn session(&self) -> Result<Session, String> {
+        serde_json::from_value(schema::migrate(self.session.clone())?)
+            .map_err(|e| format!("Exported session is invalid: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SourceKind;
+    use std::collections::BTreeMap;
+
+    fn exported() -> SessionFile {
+        let env = BTreeMap::from([("HOME".to_string(), "/home/u".to_string())]);
+        let mut session = Session::new(&env).unwrap();
+        session.capture_originals(&env);
+        session.record("set", None, [("FOO".to_string(), None, Some("bar".to_string()))]);
+        session.track_set("FOO", "bar", None, SourceKind::Set);
+        session.save_snapshot(Some("before"), &BTreeMap::new()).unwrap();
+        let profile = ProfileInfo { name: "dev".into(), checksum: Some("42".into()) };
+        SessionFile::new(&session, Some(profile)).unwrap()
+    }
+
+    #[test]
+    fn roundtrip_keeps_tracked_changes_only() {
+        let text = serde_json::to_string(&exported()).unwrap();
+        let file = SessionFile::parse(&text).unwrap();
+        let session = file.session().unwrap();
+
+        assert!(session.tracked.contains_key("FOO"));
+        assert!(session.snapshots.is_empty());
+        assert!(session.journal.is_empty());
+        assert!(session.undo_stack.is_empty());
+        assert!(session.originals.is_none() && session.owner.is_none() && session.baseline.is_empty());
+        assert_eq!(session.fingerprint, Scheme::default());
+        assert_eq!(file.profile.unwrap().name, "dev");
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(SessionFile::parse("{}").is_err());
+        let mut file = exported();
+        file.format = "something-else".into();
+        let err = SessionFile::parse(&serde_json::to_string(&file).unwrap()).unwrap_err();
+        assert!(err.contains("not an exported envision session"));
+    }
+
+    #[test]
+    fn rejects_newer_format() {
+        let mut file = exported();
+        file.version = FORMAT_VERSION + 1;
+        let err = SessionFile::parse(&serde_json::to_string(&file).unwrap()).unwrap_err();
+        assert!(err.contains("newer envision"));
+    }
+
+    #[test]
+    fn write_refuses_to_overwrite() {
+        let path = std::env::temp_dir().join(format!("envision-export-{}.json", std::process::id()));
+        let file = exported();
+        file.write(&path, false).unwrap();
+        assert!(file.write(&path, false).unwrap_err().contains("--force"));
+        file.write(&path, true).unwrap();
+        assert!(SessionFile::read(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
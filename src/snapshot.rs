@@ -0,0 +1,212 @@
+use crate::commands::profile::{CHECKSUM_VAR, PROFILE_VAR};
+use crate::session::{TrackedChange, is_envision_var, is_sensitive_var};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A saved copy of the shell environment and the session's tracked changes.
+/// Sensitive variables (see `is_sensitive_var`) are never copied.
+/// 00-R3: identified by sequence number, optionally by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub seq: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub created_at: u64,
+    /// Taken automatically before a mutating command; subject to pruning.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto: bool,
+    /// What the snapshot was taken for, e.g. `before set FOO`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Full environment at capture time, minus session bookkeeping vars
+    /// and sensitive ones.
+    pub env: BTreeMap<String, String>,
+    /// Tracked changes at capture time, reinstated on restore. Changes to
+    /// sensitive variables are left out like their values.
+    pub tracked: BTreeMap<String, TrackedChange>,
+}
+
+impl Snapshot {
+    pub fn capture(
+        seq: u32,
+        name: Option<&str>,
+        created_at: u64,
+        env: &BTreeMap<String, String>,
+        tracked: &BTreeMap<String, TrackedChange>,
+    ) -> Self {
+        let env = env
+            .iter()
+            .filter(|(k, _)| !is_session_managed(k) && !is_sensitive_var(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let tracked = tracked
+            .iter()
+            .filter(|(k, _)| !is_sensitive_var(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        Self {
+            seq,
+            name: name.map(str::to_string),
+            created_at,
+            auto: false,
+            note: None,
+            env,
+            tracked,
+        }
+    }
+
+    /// Display label: `#3`, `#3 (before-upgrade)` or `#3 (auto: before clear)`.
+    pub fn label(&self) -> String {
+        match (&self.name, &self.note) {
+            (Some(name), _) => format!("#{} ({name})", self.seq),
+            (None, Some(note)) if self.auto => format!("#{} (auto: {note})", self.seq),
+            (None, _) => format!("#{}", self.seq),
+        }
+    }
+
+    /// Match a user-supplied reference: a name, a sequence number or `#N`.
+    pub fn matches(&self, key: &str) -> bool {
+        if self.name.as_deref() == Some(key) {
+            return true;
+        }
+        key.trim_start_matches('#')
+            .parse::<u32>()
+            .is_ok_and(|seq| seq == self.seq)
+    }
+}
+
+/// Compute the statements needed to turn `current` into the snapshot's env.
+/// Each entry is a variable and its target value (`None` = unset).
+pub fn restore_plan(
+    current: &BTreeMap<String, String>,
+    snapshot: &Snapshot,
+) -> Vec<(String, Option<String>)> {
+    let mut plan = Vec::new();
+
+    for (var, value) in &snapshot.env {
+        if current.get(var) != Some(value) {
+            plan.push((var.clone(), Some(value.clone())));
+        }
+    }
+
+    for var in current.keys() {
+        // Never captured, so their absence means nothing
+        if is_session_managed(var) || is_sensitive_var(var) {
+            continue;
+        }
+        if !snapshot.env.contains_key(var) {
+            plan.push((var.clone(), None));
+        }
+    }
+
+    plan.sort();
+    plan
+}
+
+/// Validate a snapshot name. Purely numeric names would shadow sequence numbers.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Snapshot name cannot be empty".into());
+    }
+    // Reserved for the other sides of `envision diff`
+    if ["baseline", "live"].contains(&name) {
+        return Err(format!("Invalid snapshot name '{name}': the name is reserved"));
+    }
+    if name.trim_start_matches('#').chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "Invalid snapshot name '{name}': names cannot be numbers (those refer to sequence numbers)"
+        ));
+    }
+    if let Some(c) = name.chars().find(|c| !c.is_ascii_alphanumeric() && !"-_.".contains(*c)) {
+        return Err(format!(
+            "Invalid snapshot name '{name}': contains invalid character '{c}'"
+        ));
+    }
+    Ok(())
+}
+
+/// Variables owned by the session itself, never captured or restored. The
+/// profile markers are the exception: restoring a snapshot brings back the
+/// profile it was taken under.
+fn is_session_managed(var: &str) -> bool {
+    is_envision_var(var) && var != PROFILE_VAR && var != CHECKSUM_VAR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export;
+    use crate::owner::SHELL_PID_VAR;
+    use crate::session::SESSION_VAR;
+    use crate::testutil::env;
+
+    #[test]
+    fn capture_skips_session_vars() {
+        let snap = Snapshot::capture(
+            1, None, 0,
+            &env(&[
+                ("FOO", "bar"), (SESSION_VAR, "data"), (export::DIRTY_VAR, "0"),
+                (export::SESSION_NAME_VAR, "work"), (SHELL_PID_VAR, "42"), (PROFILE_VAR, "dev"),
+            ]),
+            &BTreeMap::new(),
+        );
+        assert_eq!(snap.env, env(&[("FOO", "bar"), (PROFILE_VAR, "dev")]));
+    }
+
+    #[test]
+    fn capture_skips_sensitive_vars() {
+        let tracked = BTreeMap::from([(
+            "API_TOKEN".to_string(),
+            TrackedChange::Set { value: "secret".into(), previous: None, source: None },
+        )]);
+        let snap = Snapshot::capture(1, None, 0, &env(&[("FOO", "bar"), ("API_TOKEN", "secret")]), &tracked);
+        assert_eq!(snap.env, env(&[("FOO", "bar")]));
+        assert!(snap.tracked.is_empty());
+
+        // Restoring leaves them alone rather than unsetting them
+        let plan = restore_plan(&env(&[("FOO", "bar"), ("API_TOKEN", "other")]), &snap);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn matches_by_name_and_seq() {
+        let snap = Snapshot::capture(3, Some("before"), 0, &BTreeMap::new(), &BTreeMap::new());
+        assert!(snap.matches("before"));
+        assert!(snap.matches("3"));
+        assert!(snap.matches("#3"));
+        assert!(!snap.matches("4"));
+        assert_eq!(snap.label(), "#3 (before)");
+    }
+
+    #[test]
+    fn restore_plan_sets_and_unsets() {
+        let snap = Snapshot::capture(
+            1, None, 0,
+            &env(&[("KEEP", "same"), ("CHANGED", "old"), ("GONE", "back")]),
+            &BTreeMap::new(),
+        );
+        let current = env(&[
+            ("KEEP", "same"),
+            ("CHANGED", "new"),
+            ("ADDED", "x"),
+            (SESSION_VAR, "data"),
+        ]);
+        let plan = restore_plan(&current, &snap);
+        assert_eq!(plan, vec![
+            ("ADDED".to_string(), None),
+            ("CHANGED".to_string(), Some("old".to_string())),
+            ("GONE".to_string(), Some("back".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn snapshot_names() {
+        assert!(validate_name("before-upgrade").is_ok());
+        assert!(validate_name("v1.2_rc").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("42").is_err());
+        assert!(validate_name("#7").is_err());
+        assert!(validate_name("has space").is_err());
+    }
+}
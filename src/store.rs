@@ -0,0 +1,152 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Prefix marking `ENVISION_SESSION` as a pointer to an on-disk session.
+pub const FILE_PREFIX: &str = "file:";
+
+/// Where session data lives between commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// Entire session encoded in the ENVISION_SESSION variable.
+    #[default]
+    Env,
+    /// Session file under $XDG_STATE_HOME/envision, pointer in the environment.
+    File,
+}
+
+impl Storage {
+    pub fn is_env(&self) -> bool {
+        *self == Storage::Env
+    }
+}
+
+/// 00-R5: per-user state directory, `$XDG_STATE_HOME/envision`
+/// (falling back to `~/.local/state/envision`).
+pub fn state_dir() -> Result<PathBuf, String> {
+    let base = match std::env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var("HOME")
+                .map_err(|_| "Cannot locate session storage: neither XDG_STATE_HOME nor HOME is set")?;
+            PathBuf::from(home).join(".local").join("state")
+        }
+    };
+    Ok(base.join("envision"))
+}
+
+/// 00-R5: per-session file inside the state directory.
+pub fn session_path(id: &str) -> Result<PathBuf, String> {
+    validate_id(id)?;
+    Ok(state_dir()?.join(format!("{id}.session")))
+}
+
+/// Write session data with 0600 permissions, replacing any previous file
+/// atomically. 01-R4, 01-R11
+pub fn write(id: &str, data: &str) -> Result<PathBuf, String> {
+    write_in(&state_dir()?, id, data)
+}
+
+fn write_in(dir: &Path, id: &str, data: &str) -> Result<PathBuf, String> {
+    validate_id(id)?;
+    let path = dir.join(format!("{id}.session"));
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(|e| format!("Cannot create session storage at {}: {e}", dir.display()))?;
+
+    let tmp = path.with_extension("session.tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .map_err(|e| format!("Cannot write session file {}: {e}", tmp.display()))?;
+    file.write_all(data.as_bytes())
+        .map_err(|e| format!("Cannot write session file {}: {e}", tmp.display()))?;
+    fs::rename(&tmp, &path)
+        .map_err(|e| format!("Cannot write session file {}: {e}", path.display()))?;
+
+    Ok(path)
+}
+
+pub fn read(id: &str) -> Result<String, String> {
+    let path = session_path(id)?;
+    fs::read_to_string(&path).map_err(|e| {
+        format!(
+            "Cannot read session file {}: {e}. Run 'envision session init --force' to start over.",
+            path.display()
+        )
+    })
+}
+
+/// IDs of all session files in the state directory.
+pub fn list() -> Result<Vec<String>, String> {
+    let dir = state_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Cannot read session storage at {}: {e}", dir.display())),
+    };
+    let mut ids: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_suffix(".session").map(str::to_string)
+        })
+        .filter(|id| validate_id(id).is_ok())
+        .collect();
+    ids.sort();
+    Ok(ids)
+}
+
+/// Remove a session file; a missing file is not an error.
+pub fn remove(id: &str) -> Result<(), String> {
+    let path = session_path(id)?;
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Cannot remove session file {}: {e}", path.display())),
+    }
+}
+
+/// Session IDs become file names; reject anything that could escape the
+/// state directory.
+fn validate_id(id: &str) -> Result<(), String> {
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Ok(())
+    } else {
+        Err(format!("Invalid session id '{id}' in session pointer"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("envision-test-{}", std::process::id()));
+        let path = write_in(&dir, "abc123", "data").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ids_cannot_traverse_paths() {
+        assert!(validate_id("c111ac57").is_ok());
+        assert!(validate_id("").is_err());
+        assert!(validate_id("../etc/passwd").is_err());
+        assert!(validate_id("a/b").is_err());
+    }
+}
@@ -0,0 +1,6 @@
+use std::collections::BTreeMap;
+
+/// An environment built from `(name, value)` pairs.
+pub fn env(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}